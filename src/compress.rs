@@ -0,0 +1,280 @@
+use async_compression::tokio_02::bufread::{BrotliEncoder, GzipEncoder, ZstdEncoder};
+use bytes::BytesMut;
+use futures::TryStreamExt;
+use hyper::body::{Body, HttpBody};
+use mime::Mime;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tokio::io::{self, AsyncRead};
+use tokio_util::codec::{BytesCodec, FramedRead};
+
+use crate::config::{self, Encoding};
+
+/// Compresses responses for clients that accept it.
+#[derive(Debug)]
+pub struct Compression {
+    encodings: Vec<Encoding>,
+    min_size: u64,
+}
+
+impl Compression {
+    pub fn new(config: config::CompressionConfig) -> Self {
+        Compression {
+            encodings: config.encodings,
+            min_size: config.min_size,
+        }
+    }
+
+    /// Compresses a response with the encoding preferred by the client, if its body is large
+    /// enough and of a type worth compressing.
+    pub fn compress(
+        &self,
+        accept_encoding: Option<&http::HeaderValue>,
+        mut response: http::Response<Body>,
+    ) -> http::Response<Body> {
+        if !self.should_compress(&response) {
+            return response;
+        }
+        add_vary(&mut response);
+
+        let encoding = match negotiate(accept_encoding, &self.encodings) {
+            Some(encoding) => encoding,
+            None => return response,
+        };
+
+        // Ranges of the compressed body can't be served, since it is produced as it is sent.
+        let (mut parts, body) = response.into_parts();
+        parts.headers.remove(http::header::CONTENT_LENGTH);
+        parts.headers.remove(http::header::ACCEPT_RANGES);
+        parts.headers.insert(
+            http::header::CONTENT_ENCODING,
+            http::HeaderValue::from_static(encoding.token()),
+        );
+        // The compressed body is a different representation, so it needs a different tag. Adding
+        // a suffix keeps the tag strong, so `If-Match` still works; the suffix is removed again
+        // from `If-Match` and `If-None-Match` by `strip_etag_suffixes`.
+        if let Some(etag) = parts.headers.get_mut(http::header::ETAG) {
+            if let Some(encoded) = encoded_etag(etag, encoding) {
+                *etag = encoded;
+            }
+        }
+
+        let reader = io::stream_reader(body.map_err(io::Error::other));
+        let body = match encoding {
+            Encoding::Gzip => encoded_body(GzipEncoder::new(reader)),
+            Encoding::Brotli => encoded_body(BrotliEncoder::new(reader)),
+            Encoding::Zstd => encoded_body(ZstdEncoder::new(reader)),
+        };
+        http::Response::from_parts(parts, body)
+    }
+
+    /// Removes the suffixes added by `compress` from the entity tags in a request's `If-Match` and
+    /// `If-None-Match` headers, so that handlers can compare them with the tags of their
+    /// uncompressed representations. `If-Range` is left alone, since a range of the uncompressed
+    /// representation can't resume a compressed download.
+    pub fn strip_etag_suffixes(&self, headers: &mut http::HeaderMap) {
+        static SUFFIX_REGEX: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r#";(?:gzip|br|zstd)""#).unwrap());
+
+        for name in &[http::header::IF_MATCH, http::header::IF_NONE_MATCH] {
+            if let Some(value) = headers.get_mut(name) {
+                let stripped = match value.to_str() {
+                    Ok(value) => SUFFIX_REGEX.replace_all(value, "\"").into_owned(),
+                    Err(_) => continue,
+                };
+                if let Ok(stripped) = http::HeaderValue::from_str(&stripped) {
+                    *value = stripped;
+                }
+            }
+        }
+    }
+
+    fn should_compress(&self, response: &http::Response<Body>) -> bool {
+        let status = response.status();
+        if status.is_informational()
+            || status == http::StatusCode::NO_CONTENT
+            || status == http::StatusCode::NOT_MODIFIED
+            || status == http::StatusCode::PARTIAL_CONTENT
+            || response
+                .headers()
+                .contains_key(http::header::CONTENT_ENCODING)
+        {
+            return false;
+        }
+
+        // Bodies of unknown length, such as event streams, are left alone.
+        let len = response.body().size_hint().exact().or_else(|| {
+            response
+                .headers()
+                .get(http::header::CONTENT_LENGTH)?
+                .to_str()
+                .ok()?
+                .parse()
+                .ok()
+        });
+        match len {
+            Some(len) if len >= self.min_size => (),
+            _ => return false,
+        }
+
+        response
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok()?.parse::<Mime>().ok())
+            .is_some_and(|mime| is_compressible(&mime))
+    }
+}
+
+impl Encoding {
+    /// The content coding, as used in `Accept-Encoding` and `Content-Encoding`.
+    pub fn token(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    /// The extension of precompressed files with this encoding.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gz",
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zst",
+        }
+    }
+}
+
+/// Chooses the encoding from `available` with the highest quality in an `Accept-Encoding` header,
+/// preferring earlier encodings in `available` when qualities are equal.
+pub fn negotiate(
+    accept_encoding: Option<&http::HeaderValue>,
+    available: &[Encoding],
+) -> Option<Encoding> {
+    let accept_encoding = accept_encoding?.to_str().ok()?;
+    let codings: Vec<(&str, f32)> = accept_encoding
+        .split(',')
+        .filter_map(|coding| {
+            let mut params = coding.split(';');
+            let name = params.next()?.trim();
+            let quality = params
+                .filter_map(|param| {
+                    let (key, value) = param.trim().split_at(param.trim().find('=')?);
+                    if key.eq_ignore_ascii_case("q") {
+                        value[1..].trim().parse().ok()
+                    } else {
+                        None
+                    }
+                })
+                .next()
+                .unwrap_or(1.0);
+            Some((name, quality))
+        })
+        .collect();
+
+    let quality = |token: &str| {
+        codings
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(token))
+            .or_else(|| codings.iter().find(|(name, _)| *name == "*"))
+            .map_or(0.0, |(_, quality)| *quality)
+    };
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for &encoding in available {
+        let quality = quality(encoding.token());
+        match best {
+            Some((_, best)) if best >= quality => (),
+            _ if quality > 0.0 => best = Some((encoding, quality)),
+            _ => (),
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Adds `Accept-Encoding` to the `Vary` header of a response.
+pub fn add_vary(response: &mut http::Response<Body>) {
+    let varies = response
+        .headers()
+        .get_all(http::header::VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|name| {
+            let name = name.trim();
+            name == "*" || name.eq_ignore_ascii_case("accept-encoding")
+        });
+    if !varies {
+        response.headers_mut().append(
+            http::header::VARY,
+            http::HeaderValue::from_static("accept-encoding"),
+        );
+    }
+}
+
+/// Adds the encoding to an entity tag, turning `"tag"` into `"tag;br"`. The `;` separator tells
+/// the suffix apart from the `-gzip` that upstream servers such as Apache's mod_deflate add, so
+/// only suffixes added by this server are stripped from requests.
+fn encoded_etag(etag: &http::HeaderValue, encoding: Encoding) -> Option<http::HeaderValue> {
+    let etag = etag.to_str().ok()?.strip_suffix('"')?;
+    http::HeaderValue::from_str(&format!("{};{}\"", etag, encoding.token())).ok()
+}
+
+fn encoded_body<R>(encoder: R) -> Body
+where
+    R: AsyncRead + Send + 'static,
+{
+    Body::wrap_stream(FramedRead::new(encoder, BytesCodec::new()).map_ok(BytesMut::freeze))
+}
+
+fn is_compressible(mime: &Mime) -> bool {
+    match (mime.type_(), mime.subtype()) {
+        (mime::TEXT, subtype) => subtype != mime::EVENT_STREAM,
+        (mime::IMAGE, mime::SVG) => true,
+        (mime::APPLICATION, subtype) => {
+            matches!(
+                subtype.as_str(),
+                "json" | "javascript" | "xml" | "wasm" | "x-yaml" | "toml" | "problem+json"
+            ) || mime.suffix() == Some(mime::JSON)
+                || mime.suffix() == Some(mime::XML)
+        }
+        _ => false,
+    }
+}
+
+#[test]
+fn test_etag_suffixes() {
+    let etag = http::HeaderValue::from_static("\"abc\"");
+    let encoded = encoded_etag(&etag, Encoding::Brotli).unwrap();
+    assert_eq!(encoded, "\"abc;br\"");
+
+    let compression = Compression::new(config::CompressionConfig {
+        encodings: vec![Encoding::Gzip],
+        min_size: 0,
+    });
+    let mut headers = http::HeaderMap::new();
+    headers.insert(http::header::IF_MATCH, encoded);
+    headers.insert(
+        http::header::IF_NONE_MATCH,
+        "W/\"a;gzip\", \"b-gzip\"".parse().unwrap(),
+    );
+    headers.insert(http::header::IF_RANGE, "\"c;gzip\"".parse().unwrap());
+    compression.strip_etag_suffixes(&mut headers);
+    assert_eq!(headers[http::header::IF_MATCH], "\"abc\"");
+    assert_eq!(headers[http::header::IF_NONE_MATCH], "W/\"a\", \"b-gzip\"");
+    assert_eq!(headers[http::header::IF_RANGE], "\"c;gzip\"");
+}
+
+#[test]
+fn test_negotiate() {
+    fn negotiate_str(accept_encoding: &str) -> Option<Encoding> {
+        let available = [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
+        negotiate(Some(&accept_encoding.parse().unwrap()), &available)
+    }
+
+    assert_eq!(negotiate_str("gzip, deflate, br"), Some(Encoding::Brotli));
+    assert_eq!(negotiate_str("gzip;q=1.0, br;q=0.5"), Some(Encoding::Gzip));
+    assert_eq!(negotiate_str("br;q=0, *"), Some(Encoding::Zstd));
+    assert_eq!(negotiate_str("identity"), None);
+    assert_eq!(negotiate_str("*;q=0"), None);
+}
@@ -1,3 +1,4 @@
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::BufReader;
 use std::path::PathBuf;
@@ -5,9 +6,12 @@ use std::path::PathBuf;
 use anyhow::{bail, Result};
 use fn_error_context::context;
 use http::uri::Uri;
+use mime::Mime;
+use serde::de::{self, Deserializer};
 use serde::Deserialize;
 use structopt::StructOpt;
 
+use crate::glob::Glob;
 use crate::method::MethodSet;
 use crate::route;
 
@@ -24,15 +28,58 @@ pub struct Options {
 #[context("failed to parse config from `{}`", options.config.display())]
 pub fn parse(options: &Options) -> Result<Config> {
     let reader = BufReader::new(File::open(&options.config)?);
-    let config: Config = serde_yaml::from_reader(reader)?;
+    let mut config: Config = serde_yaml::from_reader(reader)?;
     log::debug!("{:#?}", config);
     config.validate()?;
+    config.inherit_content_types();
     Ok(config)
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct Config {
     pub routes: Vec<Route>,
+    pub sessions: Option<SessionConfig>,
+    pub compression: Option<CompressionConfig>,
+    /// Content types for file extensions, used by all file and dir routes.
+    #[serde(default, deserialize_with = "deserialize_mime_types")]
+    pub mime_types: HashMap<String, Mime>,
+    pub default_charset: Option<String>,
+    #[serde(default)]
+    pub sniff: bool,
+}
+
+/// Compresses responses from every route, for clients that accept it.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CompressionConfig {
+    /// The encodings to use, in order of preference.
+    #[serde(default = "default_compression_encodings")]
+    pub encodings: Vec<Encoding>,
+    /// Responses smaller than this many bytes are sent uncompressed.
+    #[serde(default = "default_compression_min_size")]
+    pub min_size: u64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    Gzip,
+    #[serde(rename = "br", alias = "brotli")]
+    Brotli,
+    Zstd,
+}
+
+/// Lets clients work on isolated copies of the data of json routes, by sending a session id in a
+/// header or cookie.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SessionConfig {
+    #[serde(default = "default_session_header")]
+    pub header: String,
+    pub cookie: Option<String>,
+    #[serde(default = "default_session_idle_timeout_secs")]
+    pub idle_timeout_secs: u64,
 }
 
 #[derive(Debug, Deserialize)]
@@ -44,43 +91,219 @@ pub struct Route {
     pub methods: Option<MethodSet>,
     #[serde(with = "http_serde::header_map", default)]
     pub response_headers: http::HeaderMap,
+    pub max_concurrent: Option<ConcurrencyLimit>,
+    pub request_schema: Option<PathBuf>,
     #[serde(flatten)]
     pub kind: RouteKind,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConcurrencyLimit {
+    pub limit: usize,
+    pub queue_timeout_ms: Option<u64>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(tag = "kind", rename_all = "kebab-case")]
-#[serde(deny_unknown_fields)] 
+#[serde(deny_unknown_fields)]
 pub enum RouteKind {
     Dir(DirRoute),
     File(FileRoute),
+    Archive(ArchiveRoute),
     Proxy(ProxyRoute),
     Json(JsonRoute),
     Mock(MockRoute),
+    Admin(AdminRoute),
 }
 
 #[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)] 
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct DirRoute {
     pub path: PathBuf,
+    pub cache_control: Option<String>,
+    pub listing: Option<DirListing>,
+    #[serde(default)]
+    pub index: Vec<String>,
+    pub spa_fallback: Option<PathBuf>,
+    /// Allows files to be uploaded with `PUT` and removed with `DELETE`. Files are only ever
+    /// written within the route's directory, even if `symlinks` is set to `follow`.
+    #[serde(default)]
+    pub writable: bool,
+    /// Allows directories to be created, either with `MKCOL` or by uploading a file to a
+    /// directory that doesn't exist yet. Requires `writable`.
+    #[serde(default)]
+    pub mkcol: bool,
+    /// Allows `DELETE` to remove a directory and everything in it. Otherwise only empty
+    /// directories can be removed. Requires `writable`.
+    #[serde(default)]
+    pub recursive_delete: bool,
+    #[serde(default = "default_dir_max_upload_size")]
+    pub max_upload_size: u64,
+    /// Content types for file extensions, overriding those guessed from the extension.
+    #[serde(default, deserialize_with = "deserialize_mime_types")]
+    pub mime_types: HashMap<String, Mime>,
+    /// The charset added to the content type of text files.
+    pub default_charset: Option<String>,
+    /// Whether to guess the content type of files without an extension from their content.
+    pub sniff: Option<bool>,
+    /// How to treat files and directories whose names start with a `.`. If unset, they are
+    /// served but left out of listings.
+    pub dotfiles: Option<Dotfiles>,
+    #[serde(default)]
+    pub symlinks: Symlinks,
+    /// If not empty, only files matching one of these patterns are served.
+    #[serde(default)]
+    pub allow: Vec<Glob>,
+    /// Files and directories matching any of these patterns are never served.
+    #[serde(default)]
+    pub deny: Vec<Glob>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dotfiles {
+    /// Serve and list them like any other file.
+    Allow,
+    /// Respond as if they don't exist.
+    Ignore,
+    /// Respond with `403 Forbidden`.
+    Deny,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Symlinks {
+    #[default]
+    Follow,
+    /// Follow symlinks only if their target is within the route's directory.
+    Confine,
+    Deny,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirListing {
+    Html,
+    Json,
 }
 
 #[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)] 
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct FileRoute {
     pub path: PathBuf,
+    pub cache_control: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_mime_types")]
+    pub mime_types: HashMap<String, Mime>,
+    pub default_charset: Option<String>,
+    pub sniff: Option<bool>,
 }
 
+/// Serves the files in a `.zip`, `.tar` or `.tar.gz` archive, without extracting it.
+/// The archive is read again when its modification time or size changes.
 #[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)] 
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ArchiveRoute {
+    pub path: PathBuf,
+    pub cache_control: Option<String>,
+    #[serde(default)]
+    pub index: Vec<String>,
+    /// A file in the archive to serve for paths without an extension that don't exist.
+    pub spa_fallback: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_mime_types")]
+    pub mime_types: HashMap<String, Mime>,
+    pub default_charset: Option<String>,
+    pub sniff: Option<bool>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct JsonRoute {
+    pub name: Option<String>,
     pub path: PathBuf,
     #[serde(default)]
     pub pretty: bool,
+    #[serde(default)]
+    pub id: JsonId,
+    #[serde(default)]
+    pub debounce_ms: u64,
+    #[serde(default)]
+    pub watch: bool,
+    #[serde(default = "default_json_watch_interval_ms")]
+    pub watch_interval_ms: u64,
+    #[serde(default = "default_json_conflict_policy")]
+    pub on_conflict: JsonConflictPolicy,
+    /// The format of the backing file. YAML and TOML files are rewritten from the data when it
+    /// changes, so comments and formatting in them are not preserved.
+    pub format: Option<JsonFormat>,
+    pub schema: Option<PathBuf>,
+    #[serde(default)]
+    pub relations: Vec<JsonRelation>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JsonFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JsonId {
+    #[serde(default = "default_json_id_field")]
+    pub field: String,
+    #[serde(default = "default_json_id_strategy")]
+    pub strategy: JsonIdStrategy,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JsonIdStrategy {
+    Increment,
+    Uuid,
+    None,
+}
+
+/// Which version wins when a json route's file is changed on disk while there are in-memory
+/// changes that have not yet been written.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JsonConflictPolicy {
+    File,
+    Memory,
+}
+
+/// A foreign key from items in one top-level collection to items in another, e.g.
+/// `comments.postId -> posts.id`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct JsonRelation {
+    pub from: JsonField,
+    pub to: JsonField,
+    pub expand_as: Option<String>,
+    pub embed_as: Option<String>,
+    #[serde(default)]
+    pub cascade: bool,
+}
+
+/// A field of the items in a top-level collection, written as `collection.field`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonField {
+    pub collection: String,
+    pub field: String,
 }
 
 #[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)] 
+#[serde(deny_unknown_fields)]
 pub struct MockRoute {
     #[serde(with = "http_serde::status_code")]
     pub status: http::StatusCode,
@@ -88,7 +311,11 @@ pub struct MockRoute {
 }
 
 #[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields, rename_all = "kebab-case")] 
+#[serde(deny_unknown_fields)]
+pub struct AdminRoute {}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct ProxyRoute {
     #[serde(rename = "url", with = "http_serde::uri")]
     pub uri: Uri,
@@ -96,8 +323,81 @@ pub struct ProxyRoute {
 
 impl Config {
     fn validate(&self) -> Result<()> {
+        let mut json_names = HashSet::new();
         for route in &self.routes {
             route.validate()?;
+            if let RouteKind::Json(JsonRoute {
+                name: Some(name), ..
+            }) = &route.kind
+            {
+                if !json_names.insert(name) {
+                    bail!("json route name `{}` is used more than once", name);
+                }
+            }
+        }
+        if let Some(sessions) = &self.sessions {
+            sessions.validate()?;
+        }
+        if let Some(compression) = &self.compression {
+            compression.validate()?;
+        }
+        validate_charset(&self.default_charset)?;
+        Ok(())
+    }
+
+    /// Applies the global content type settings to file and dir routes, where they don't override
+    /// them.
+    fn inherit_content_types(&mut self) {
+        for route in &mut self.routes {
+            let (mime_types, default_charset, sniff) = match &mut route.kind {
+                RouteKind::Dir(dir) => (
+                    &mut dir.mime_types,
+                    &mut dir.default_charset,
+                    &mut dir.sniff,
+                ),
+                RouteKind::File(file) => (
+                    &mut file.mime_types,
+                    &mut file.default_charset,
+                    &mut file.sniff,
+                ),
+                RouteKind::Archive(archive) => (
+                    &mut archive.mime_types,
+                    &mut archive.default_charset,
+                    &mut archive.sniff,
+                ),
+                _ => continue,
+            };
+            for (extension, mime) in &self.mime_types {
+                mime_types
+                    .entry(extension.clone())
+                    .or_insert_with(|| mime.clone());
+            }
+            if default_charset.is_none() {
+                *default_charset = self.default_charset.clone();
+            }
+            sniff.get_or_insert(self.sniff);
+        }
+    }
+}
+
+impl SessionConfig {
+    #[context("invalid session config")]
+    fn validate(&self) -> Result<()> {
+        if http::header::HeaderName::from_bytes(self.header.as_bytes()).is_err() {
+            bail!("invalid header name `{}`", self.header);
+        }
+        if self.idle_timeout_secs == 0 {
+            bail!("idle-timeout-secs must be greater than zero");
+        }
+        Ok(())
+    }
+}
+
+impl CompressionConfig {
+    #[context("invalid compression config")]
+    fn validate(&self) -> Result<()> {
+        if self.encodings.is_empty() {
+            bail!("at least one encoding must be given");
         }
         Ok(())
     }
@@ -106,13 +406,94 @@ impl Config {
 impl Route {
     #[context("error in route `{}`", self.route)]
     fn validate(&self) -> Result<()> {
+        if let Some(max_concurrent) = &self.max_concurrent {
+            max_concurrent.validate()?;
+        }
+        if let Some(request_schema) = &self.request_schema {
+            if !request_schema.is_file() {
+                bail!("`{}` is not a file", request_schema.display());
+            }
+        }
         match &self.kind {
             RouteKind::Dir(dir) => dir.validate(),
             RouteKind::File(file) => file.validate(),
+            RouteKind::Archive(archive) => archive.validate(),
             RouteKind::Proxy(proxy) => proxy.validate(),
             RouteKind::Json(json) => json.validate(),
-            RouteKind::Mock(_) => Ok(()),
+            RouteKind::Mock(_) | RouteKind::Admin(_) => Ok(()),
+        }
+    }
+}
+
+impl Default for JsonId {
+    fn default() -> Self {
+        JsonId {
+            field: default_json_id_field(),
+            strategy: default_json_id_strategy(),
+        }
+    }
+}
+
+/// Deserializes a map from file extensions to content types. Extensions are matched without their
+/// leading `.` and ignoring case.
+fn deserialize_mime_types<'de, D>(deserializer: D) -> Result<HashMap<String, Mime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HashMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(extension, mime)| {
+            let mime = mime.parse().map_err(|err| {
+                de::Error::custom(format!("invalid mime type `{}`: {}", mime, err))
+            })?;
+            let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+            Ok((extension, mime))
+        })
+        .collect()
+}
+
+fn default_session_header() -> String {
+    "x-mock-session".to_owned()
+}
+
+fn default_session_idle_timeout_secs() -> u64 {
+    600
+}
+
+fn default_compression_encodings() -> Vec<Encoding> {
+    vec![Encoding::Brotli, Encoding::Zstd, Encoding::Gzip]
+}
+
+fn default_compression_min_size() -> u64 {
+    1024
+}
+
+fn default_dir_max_upload_size() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_json_id_field() -> String {
+    "id".to_owned()
+}
+
+fn default_json_id_strategy() -> JsonIdStrategy {
+    JsonIdStrategy::Increment
+}
+
+fn default_json_watch_interval_ms() -> u64 {
+    1000
+}
+
+fn default_json_conflict_policy() -> JsonConflictPolicy {
+    JsonConflictPolicy::File
+}
+
+impl ConcurrencyLimit {
+    fn validate(&self) -> Result<()> {
+        if self.limit == 0 {
+            bail!("max-concurrent limit must be greater than zero");
         }
+        Ok(())
     }
 }
 
@@ -121,6 +502,24 @@ impl DirRoute {
         if !self.path.is_dir() {
             bail!("`{}` is not a directory", self.path.display());
         }
+        validate_cache_control(&self.cache_control)?;
+        validate_index(&self.index)?;
+        if let Some(spa_fallback) = &self.spa_fallback {
+            if !self.path.join(spa_fallback).is_file() {
+                bail!(
+                    "`{}` is not a file in `{}`",
+                    spa_fallback.display(),
+                    self.path.display()
+                );
+            }
+        }
+        if self.mkcol && !self.writable {
+            bail!("mkcol requires writable to be set");
+        }
+        if self.recursive_delete && !self.writable {
+            bail!("recursive-delete requires writable to be set");
+        }
+        validate_charset(&self.default_charset)?;
         Ok(())
     }
 }
@@ -130,10 +529,75 @@ impl FileRoute {
         if !self.path.is_file() {
             bail!("`{}` is not a file", self.path.display());
         }
+        validate_cache_control(&self.cache_control)?;
+        validate_charset(&self.default_charset)?;
+        Ok(())
+    }
+}
+
+impl ArchiveRoute {
+    /// Returns the format of the archive, from its extension.
+    pub fn format(&self) -> Option<ArchiveFormat> {
+        let name = self.path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else {
+            None
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !self.path.is_file() {
+            bail!("`{}` is not a file", self.path.display());
+        }
+        if self.format().is_none() {
+            bail!(
+                "`{}` is not a .zip, .tar or .tar.gz archive",
+                self.path.display()
+            );
+        }
+        validate_cache_control(&self.cache_control)?;
+        validate_charset(&self.default_charset)?;
+        validate_index(&self.index)?;
         Ok(())
     }
 }
 
+fn validate_charset(charset: &Option<String>) -> Result<()> {
+    if let Some(charset) = charset {
+        if format!("text/plain; charset={}", charset)
+            .parse::<Mime>()
+            .is_err()
+        {
+            bail!("invalid charset `{}`", charset);
+        }
+    }
+    Ok(())
+}
+
+/// Checks that index file names name a file directly inside a directory.
+fn validate_index(index: &[String]) -> Result<()> {
+    for index in index {
+        if index.is_empty() || index.contains(&['/', '\\'][..]) {
+            bail!("invalid index file name `{}`", index);
+        }
+    }
+    Ok(())
+}
+
+fn validate_cache_control(cache_control: &Option<String>) -> Result<()> {
+    if let Some(cache_control) = cache_control {
+        if http::HeaderValue::from_str(cache_control).is_err() {
+            bail!("invalid cache-control `{}`", cache_control);
+        }
+    }
+    Ok(())
+}
+
 impl ProxyRoute {
     fn validate(&self) -> Result<()> {
         if self.uri.scheme().is_none() {
@@ -147,10 +611,87 @@ impl ProxyRoute {
 }
 
 impl JsonRoute {
+    /// The format of the backing file, either set explicitly or detected from its extension.
+    pub fn format(&self) -> JsonFormat {
+        if let Some(format) = self.format {
+            return format;
+        }
+        match self.path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => JsonFormat::Yaml,
+            Some("toml") => JsonFormat::Toml,
+            _ => JsonFormat::Json,
+        }
+    }
+
+    /// Whether the data is stored in a directory, with a file for each top-level key.
+    pub fn is_dir(&self) -> bool {
+        self.path.is_dir()
+    }
+
     fn validate(&self) -> Result<()> {
-        if !self.path.is_file() {
-            bail!("`{}` is not a file", self.path.display());
+        if !self.path.is_file() && !self.path.is_dir() {
+            bail!("`{}` is not a file or directory", self.path.display());
+        }
+        if let Some(name) = &self.name {
+            if name.is_empty() || name.contains('/') || name == "reset" || name == "snapshots" {
+                bail!("invalid json route name `{}`", name);
+            }
+        }
+        if let Some(schema) = &self.schema {
+            if !schema.is_file() {
+                bail!("`{}` is not a file", schema.display());
+            }
+        }
+        if self.watch && self.watch_interval_ms == 0 {
+            bail!("watch-interval-ms must be greater than zero");
         }
         Ok(())
     }
 }
+
+impl JsonRelation {
+    /// The name used with `_expand` on items of the `from` collection to include the referenced
+    /// item. Defaults to the foreign key without an `Id` or `_id` suffix, e.g. `post` for `postId`.
+    pub fn expand_name(&self) -> &str {
+        if let Some(name) = &self.expand_as {
+            return name;
+        }
+        let field = &self.from.field;
+        ["Id", "_id"]
+            .iter()
+            .find(|suffix| field.len() > suffix.len() && field.ends_with(*suffix))
+            .map(|suffix| &field[..field.len() - suffix.len()])
+            .unwrap_or(&self.to.collection)
+    }
+
+    /// The name used with `_embed` on items of the `to` collection to include the items referring
+    /// to them. Defaults to the name of the `from` collection.
+    pub fn embed_name(&self) -> &str {
+        self.embed_as.as_deref().unwrap_or(&self.from.collection)
+    }
+}
+
+impl JsonField {
+    fn parse(field: &str) -> Result<Self> {
+        let mut parts = field.splitn(2, '.');
+        match (parts.next(), parts.next()) {
+            (Some(collection), Some(field)) if !collection.is_empty() && !field.is_empty() => {
+                Ok(JsonField {
+                    collection: collection.to_owned(),
+                    field: field.to_owned(),
+                })
+            }
+            _ => bail!("invalid field `{}`, expected `collection.field`", field),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonField {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let field = String::deserialize(deserializer)?;
+        JsonField::parse(&field).map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,100 @@
+use std::fmt::{self, Display};
+
+use regex::RegexSet;
+use serde::de::{self, Deserialize, Deserializer};
+
+/// A pattern matching paths relative to the root of a dir route. `*` and `?` match within a path
+/// segment and `**` matches any number of segments. Patterns without a `/` match a file or
+/// directory with that name at any depth, like in a `.gitignore` file.
+#[derive(Debug)]
+pub struct Glob {
+    pattern: String,
+    regex: String,
+}
+
+impl Glob {
+    pub fn new(pattern: String) -> Result<Self, impl Display> {
+        let trimmed = pattern.trim_end_matches('/');
+        if trimmed.is_empty() {
+            return Err("empty glob pattern");
+        }
+
+        let mut regex = String::with_capacity(trimmed.len() + 10);
+        regex.push('^');
+        let trimmed = match trimmed.strip_prefix('/') {
+            Some(anchored) => anchored,
+            None if !trimmed.contains('/') => {
+                regex.push_str("(?:.*/)?");
+                trimmed
+            }
+            None => trimmed,
+        };
+
+        let segments: Vec<&str> = trimmed.split('/').collect();
+        for (index, segment) in segments.iter().enumerate() {
+            let is_last = index + 1 == segments.len();
+            if *segment == "**" {
+                regex.push_str(if is_last { ".*" } else { "(?:.*/)?" });
+                continue;
+            }
+
+            for c in segment.chars() {
+                match c {
+                    '*' => regex.push_str("[^/]*"),
+                    '?' => regex.push_str("[^/]"),
+                    c => regex_syntax::escape_into(c.encode_utf8(&mut [0; 4]), &mut regex),
+                }
+            }
+            if !is_last {
+                regex.push('/');
+            }
+        }
+        regex.push('$');
+
+        Ok(Glob { pattern, regex })
+    }
+}
+
+/// Builds a set matching any of `globs`.
+pub fn regex_set(globs: &[Glob]) -> RegexSet {
+    RegexSet::new(globs.iter().map(|glob| &glob.regex)).expect("error in generated regex")
+}
+
+impl Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.pattern.fmt(f)
+    }
+}
+
+impl<'de> Deserialize<'de> for Glob {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let pattern = String::deserialize(deserializer)?;
+        Glob::new(pattern).map_err(de::Error::custom)
+    }
+}
+
+#[test]
+fn test_glob() {
+    fn is_match(pattern: &str, path: &str) -> bool {
+        let glob = Glob::new(pattern.to_owned()).unwrap_or_else(|err| panic!("{}", err));
+        regex_set(&[glob]).is_match(path)
+    }
+
+    assert!(is_match(".env", ".env"));
+    assert!(is_match(".env", "config/.env"));
+    assert!(is_match("*.pem", "keys/server.pem"));
+    assert!(!is_match("*.pem", "keys/server.pem.txt"));
+    assert!(is_match("/secret", "secret"));
+    assert!(!is_match("/secret", "public/secret"));
+    assert!(is_match("assets/*.js", "assets/app.js"));
+    assert!(!is_match("assets/*.js", "assets/lib/app.js"));
+    assert!(is_match("assets/**/*.js", "assets/lib/app.js"));
+    assert!(is_match("assets/**/*.js", "assets/app.js"));
+    assert!(is_match("node_modules/", "node_modules"));
+    assert!(is_match("file?.txt", "file1.txt"));
+    assert!(is_match("a.b", "a.b"));
+    assert!(!is_match("a.b", "axb"));
+}
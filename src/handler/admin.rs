@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use hyper::Body;
+
+use super::json::{read_json, JsonRegistry};
+use crate::method::MethodFilter;
+use crate::{config, response};
+
+/// Exposes operations for managing the state of json routes.
+///
+/// Paths are interpreted relative to the route (after any path rewriting):
+///
+/// - `GET /json` lists json routes and their snapshots.
+/// - `POST /json[/{name}]/reset` restores the data loaded at startup.
+/// - `POST /json[/{name}]/snapshots/{snapshot}` saves the current data as a snapshot.
+/// - `POST /json[/{name}]/snapshots/{snapshot}/restore` restores a snapshot.
+/// - `PUT /json/{name}/seed` replaces the data with the request body.
+///
+/// Operations without a route name apply to all json routes.
+#[derive(Debug)]
+pub struct AdminHandler {
+    json: Arc<JsonRegistry>,
+}
+
+pub fn default_method_filter() -> Box<dyn MethodFilter> {
+    Box::new(|method: &http::Method| {
+        matches!(
+            *method,
+            http::Method::GET | http::Method::POST | http::Method::PUT
+        )
+    })
+}
+
+impl AdminHandler {
+    pub fn new(_: config::AdminRoute, json: Arc<JsonRegistry>) -> Self {
+        AdminHandler { json }
+    }
+
+    pub async fn handle(
+        &self,
+        request: http::Request<Body>,
+        path: &str,
+    ) -> Result<http::Response<Body>, (http::Request<Body>, http::Response<Body>)> {
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        let (name, operation) = match segments.as_slice() {
+            ["json"] => {
+                return match *request.method() {
+                    http::Method::GET => Ok(response::json(&self.json.list())),
+                    _ => Ok(response::from_status(http::StatusCode::METHOD_NOT_ALLOWED)),
+                }
+            }
+            ["json", "reset", ..] | ["json", "snapshots", ..] => (None, &segments[1..]),
+            ["json", name, rest @ ..] => (Some(*name), rest),
+            _ => return Ok(response::from_status(http::StatusCode::NOT_FOUND)),
+        };
+
+        let found = match (request.method(), operation) {
+            (&http::Method::POST, ["reset"]) => self.json.reset(name).await,
+            (&http::Method::POST, ["snapshots", snapshot]) => {
+                self.json.snapshot(name, snapshot).await
+            }
+            (&http::Method::POST, ["snapshots", snapshot, "restore"]) => {
+                self.json.restore(name, snapshot).await
+            }
+            (&http::Method::PUT, ["seed"]) => match name {
+                Some(name) => {
+                    let value = match read_json(request).await {
+                        Ok(value) => value,
+                        Err(response) => return Ok(response),
+                    };
+                    match self.json.seed(name, value).await {
+                        Ok(found) => found,
+                        Err(response) => return Ok(response),
+                    }
+                }
+                None => false,
+            },
+            (_, ["reset"])
+            | (_, ["seed"])
+            | (_, ["snapshots", _])
+            | (_, ["snapshots", _, "restore"]) => {
+                return Ok(response::from_status(http::StatusCode::METHOD_NOT_ALLOWED))
+            }
+            _ => false,
+        };
+
+        if found {
+            Ok(response::from_status(http::StatusCode::NO_CONTENT))
+        } else {
+            log::info!(
+                "Admin path `{}` did not match any json route or snapshot",
+                path
+            );
+            Ok(response::from_status(http::StatusCode::NOT_FOUND))
+        }
+    }
+}
@@ -0,0 +1,474 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::Metadata;
+use std::io::Read;
+use std::path::{self, Path};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use hyper::Body;
+use tokio::fs;
+use tokio::io;
+use tokio::task;
+use urlencoding::decode;
+use zip::ZipArchive;
+
+use super::content_type::ContentTypes;
+use super::fs::{
+    join_relative, redirect_to_dir, representation_response, sanitize_path, spa_fallback, Content,
+    FileOptions, Representation,
+};
+use crate::config::{self, ArchiveFormat};
+use crate::response;
+
+#[derive(Debug)]
+pub struct ArchiveHandler {
+    config: config::ArchiveRoute,
+    format: ArchiveFormat,
+    options: FileOptions,
+    loaded: Mutex<Arc<Loaded>>,
+    spa_fallback: Option<String>,
+}
+
+/// The contents of an archive, and the version of the file they were read from.
+#[derive(Debug)]
+struct Loaded {
+    archive: Archive,
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+/// The files in an archive, keyed by their path within it, with segments separated by `/`.
+#[derive(Debug)]
+struct Archive {
+    files: Files,
+    dirs: HashSet<String>,
+}
+
+#[derive(Debug)]
+enum Files {
+    /// Files are read from the archive when requested, using its central directory to find them.
+    Zip {
+        archive: Arc<Mutex<ZipArchive<std::fs::File>>>,
+        indices: HashMap<String, usize>,
+    },
+    /// Tar archives have no index, so their files are read into memory up front.
+    Tar(HashMap<String, Bytes>),
+}
+
+impl ArchiveHandler {
+    pub async fn new(config: config::ArchiveRoute) -> Result<Self> {
+        let format = config.format().context("unknown archive format")?;
+        let metadata = fs::metadata(&config.path).await?;
+        let loaded = Loaded::load(&config.path, format, &metadata).await?;
+
+        let spa_fallback = match &config.spa_fallback {
+            Some(spa_fallback) => match entry_key(spa_fallback) {
+                Some(key) if loaded.archive.is_file(&key) => Some(key),
+                _ => bail!(
+                    "`{}` is not a file in archive `{}`",
+                    spa_fallback,
+                    config.path.display()
+                ),
+            },
+            None => None,
+        };
+
+        let options = FileOptions {
+            cache_control: config.cache_control.clone(),
+            content_types: ContentTypes::new(
+                &config.mime_types,
+                &config.default_charset,
+                config.sniff,
+            ),
+        };
+        Ok(ArchiveHandler {
+            config,
+            format,
+            options,
+            loaded: Mutex::new(Arc::new(loaded)),
+            spa_fallback,
+        })
+    }
+
+    pub async fn handle(
+        &self,
+        request: http::Request<Body>,
+        path: &str,
+    ) -> Result<http::Response<Body>, (http::Request<Body>, http::Response<Body>)> {
+        if request.method() != http::Method::GET {
+            return Err((
+                request,
+                response::from_status(http::StatusCode::METHOD_NOT_ALLOWED),
+            ));
+        }
+
+        log::debug!(
+            "Path `{}` matched archive `{}`",
+            request.uri().path(),
+            self.config.path.display()
+        );
+
+        let path = match decode(path) {
+            Ok(path) => path,
+            Err(err) => {
+                log::info!("Invalid path `{}`: {}", path, err);
+                return Ok(response::from_status(http::StatusCode::BAD_REQUEST));
+            }
+        };
+
+        let loaded = match self.reload_if_changed().await {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                log::error!(
+                    "Failed to reload archive `{}`: {:#}",
+                    self.config.path.display(),
+                    err
+                );
+                return Ok(response::from_status(
+                    http::StatusCode::INTERNAL_SERVER_ERROR,
+                ));
+            }
+        };
+
+        match sanitize_path(&path) {
+            Some(components) => {
+                let key = components
+                    .iter()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                Ok(self.path_response(&request, &loaded, &path, &key).await)
+            }
+            None => Ok(response::from_status(http::StatusCode::NOT_FOUND)),
+        }
+    }
+
+    /// Returns the contents of the archive, reading it again if the file has been modified or
+    /// replaced since it was last read.
+    async fn reload_if_changed(&self) -> Result<Arc<Loaded>> {
+        let metadata = fs::metadata(&self.config.path).await?;
+        let loaded = self.loaded.lock().unwrap().clone();
+        if loaded.is_current(&metadata) {
+            return Ok(loaded);
+        }
+
+        log::info!("Reloading archive `{}`", self.config.path.display());
+        let loaded = Arc::new(Loaded::load(&self.config.path, self.format, &metadata).await?);
+        *self.loaded.lock().unwrap() = loaded.clone();
+        Ok(loaded)
+    }
+
+    async fn path_response(
+        &self,
+        request: &http::Request<Body>,
+        loaded: &Loaded,
+        path: &str,
+        key: &str,
+    ) -> http::Response<Body> {
+        let archive = &loaded.archive;
+        if archive.dirs.contains(key) {
+            let index = self
+                .config
+                .index
+                .iter()
+                .map(|index| join_relative(key, index))
+                .find(|index| archive.is_file(index));
+            if let Some(index) = index {
+                if let Some(response) = redirect_to_dir(request) {
+                    return response;
+                }
+                return self.file_response(request, loaded, &index).await;
+            }
+        } else if archive.is_file(key) {
+            return self.file_response(request, loaded, key).await;
+        }
+
+        if let Some(spa_fallback) = spa_fallback(self.spa_fallback.as_deref(), path) {
+            return self.file_response(request, loaded, spa_fallback).await;
+        }
+
+        log::info!("File not found in archive: `{}`", key);
+        response::from_status(http::StatusCode::NOT_FOUND)
+    }
+
+    async fn file_response(
+        &self,
+        request: &http::Request<Body>,
+        loaded: &Loaded,
+        key: &str,
+    ) -> http::Response<Body> {
+        log::debug!("Returning file `{}` from archive", key);
+
+        let data = match loaded.archive.read(key).await {
+            Ok(Some(data)) => data,
+            Ok(None) => return response::from_status(http::StatusCode::NOT_FOUND),
+            Err(err) => {
+                log::error!(
+                    "Error reading `{}` from archive `{}`: {}",
+                    key,
+                    self.config.path.display(),
+                    err
+                );
+                return response::from_status(http::StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+
+        let path = Path::new(key);
+        let representation = Representation {
+            len: data.len() as u64,
+            modified: loaded.modified,
+            mime: self.options.content_types.resolve_data(path, &data),
+            content: Content::Data(data),
+        };
+        representation_response(request.headers(), path, representation, &self.options).await
+    }
+}
+
+impl Loaded {
+    async fn load(path: &Path, format: ArchiveFormat, metadata: &Metadata) -> Result<Self> {
+        let archive_path = path.to_owned();
+        let archive = task::spawn_blocking(move || Archive::load(&archive_path, format))
+            .await?
+            .with_context(|| format!("failed to read archive `{}`", path.display()))?;
+        Ok(Loaded {
+            archive,
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+
+    /// Returns whether the archive file still has the modification time and size it had when it
+    /// was read.
+    fn is_current(&self, metadata: &Metadata) -> bool {
+        self.modified == metadata.modified().ok() && self.len == metadata.len()
+    }
+}
+
+impl Archive {
+    fn load(path: &Path, format: ArchiveFormat) -> Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let mut dirs = HashSet::new();
+        let files = match format {
+            ArchiveFormat::Zip => load_zip(file, &mut dirs)?,
+            ArchiveFormat::Tar => load_tar(file, &mut dirs)?,
+            ArchiveFormat::TarGz => load_tar(GzDecoder::new(file), &mut dirs)?,
+        };
+
+        // Not all archives have entries for directories, so add the parents of every file.
+        let keys: Vec<&String> = match &files {
+            Files::Zip { indices, .. } => indices.keys().collect(),
+            Files::Tar(files) => files.keys().collect(),
+        };
+        dirs.insert(String::new());
+        for key in keys {
+            for (index, _) in key.match_indices('/') {
+                dirs.insert(key[..index].to_owned());
+            }
+        }
+
+        Ok(Archive { files, dirs })
+    }
+
+    fn is_file(&self, key: &str) -> bool {
+        match &self.files {
+            Files::Zip { indices, .. } => indices.contains_key(key),
+            Files::Tar(files) => files.contains_key(key),
+        }
+    }
+
+    async fn read(&self, key: &str) -> io::Result<Option<Bytes>> {
+        match &self.files {
+            Files::Zip { archive, indices } => {
+                let index = match indices.get(key) {
+                    Some(&index) => index,
+                    None => return Ok(None),
+                };
+                let archive = archive.clone();
+                task::spawn_blocking(move || {
+                    let mut archive = archive.lock().unwrap();
+                    let mut file = archive.by_index(index)?;
+                    // The size in the entry's header may be wrong, so it isn't preallocated.
+                    let mut data = Vec::new();
+                    file.read_to_end(&mut data)?;
+                    Ok(Some(Bytes::from(data)))
+                })
+                .await?
+            }
+            Files::Tar(files) => Ok(files.get(key).cloned()),
+        }
+    }
+}
+
+fn load_zip(file: std::fs::File, dirs: &mut HashSet<String>) -> Result<Files> {
+    let mut archive = ZipArchive::new(file)?;
+    let mut indices = HashMap::new();
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index)?;
+        let key = match entry_key(entry.name()) {
+            Some(key) => key,
+            None => {
+                log::warn!(
+                    "Skipping archive entry with invalid name `{}`",
+                    entry.name()
+                );
+                continue;
+            }
+        };
+        if entry.is_dir() {
+            dirs.insert(key);
+        } else {
+            indices.insert(key, index);
+        }
+    }
+
+    Ok(Files::Zip {
+        archive: Arc::new(Mutex::new(archive)),
+        indices,
+    })
+}
+
+fn load_tar<R: Read>(reader: R, dirs: &mut HashSet<String>) -> Result<Files> {
+    let mut archive = tar::Archive::new(reader);
+    let mut files = HashMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let key = match path.to_str().and_then(entry_key) {
+            Some(key) => key,
+            None => {
+                log::warn!(
+                    "Skipping archive entry with invalid name `{}`",
+                    path.display()
+                );
+                continue;
+            }
+        };
+
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_dir() {
+            dirs.insert(key);
+        } else if entry_type.is_file() {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            files.insert(key, Bytes::from(data));
+        }
+    }
+    Ok(Files::Tar(files))
+}
+
+/// Normalizes the path of an archive entry into a key, or returns `None` if it would be outside of
+/// the archive's root.
+fn entry_key(name: &str) -> Option<String> {
+    let mut segments = Vec::new();
+    for component in Path::new(name).components() {
+        match component {
+            path::Component::Normal(segment) => segments.push(segment.to_str()?),
+            path::Component::CurDir => (),
+            path::Component::RootDir | path::Component::Prefix(_) | path::Component::ParentDir => {
+                return None
+            }
+        }
+    }
+    Some(segments.join("/"))
+}
+
+#[test]
+fn test_entry_key() {
+    assert_eq!(entry_key("dist/app.js").as_deref(), Some("dist/app.js"));
+    assert_eq!(entry_key("./dist/").as_deref(), Some("dist"));
+    assert_eq!(entry_key("/etc/passwd"), None);
+    assert_eq!(entry_key("../secret"), None);
+}
+
+#[tokio::test]
+async fn test_archive_handler() {
+    use std::io::{Cursor, Write};
+
+    fn zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer
+                .start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(data.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn tar(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, data.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    async fn get(
+        handler: &ArchiveHandler,
+        path: &str,
+        range: Option<&str>,
+    ) -> (http::StatusCode, Bytes) {
+        let mut request = http::Request::get(path);
+        if let Some(range) = range {
+            request = request.header(http::header::RANGE, range);
+        }
+        let request = request.body(Body::empty()).unwrap();
+        let response = handler.handle(request, path).await.unwrap();
+        let status = response.status();
+        (status, hyper::body::to_bytes(response).await.unwrap())
+    }
+
+    let files = &[
+        ("assets/app.js", "console.log(1);"),
+        ("docs/index.html", "<h1>Docs</h1>"),
+        ("index.html", "<h1>App</h1>"),
+    ];
+    let changed = &[("assets/app.js", "console.log('changed');")];
+    for (extension, build) in &[("zip", zip as fn(&[(&str, &str)]) -> Vec<u8>), ("tar", tar)] {
+        let path = std::env::temp_dir().join(format!("{}.{}", uuid::Uuid::new_v4(), extension));
+        std::fs::write(&path, build(files)).unwrap();
+        let config: config::ArchiveRoute = serde_yaml::from_str(&format!(
+            "{{ path: '{}', index: [index.htm, index.html], spa-fallback: index.html }}",
+            path.display()
+        ))
+        .unwrap();
+        let handler = ArchiveHandler::new(config).await.unwrap();
+
+        let (status, body) = get(&handler, "/assets/app.js", None).await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(body, "console.log(1);");
+        let (status, _) = get(&handler, "/assets/missing.js", None).await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+        let (status, body) = get(&handler, "/posts/1", None).await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(body, "<h1>App</h1>");
+
+        let (status, body) = get(&handler, "/docs/", None).await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(body, "<h1>Docs</h1>");
+        let (status, _) = get(&handler, "/docs", None).await;
+        assert_eq!(status, http::StatusCode::MOVED_PERMANENTLY);
+
+        let (status, body) = get(&handler, "/assets/app.js", Some("bytes=0-6")).await;
+        assert_eq!(status, http::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(body, "console");
+
+        std::fs::write(&path, build(changed)).unwrap();
+        let (status, body) = get(&handler, "/assets/app.js", None).await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(body, "console.log('changed');");
+        let (status, _) = get(&handler, "/docs/", None).await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use mime::Mime;
+use tokio::fs;
+use tokio::io::{self, AsyncReadExt};
+
+/// The number of bytes read from the start of a file to guess its content type.
+const SNIFF_LEN: u64 = 512;
+
+/// Settings for choosing the content type of files.
+#[derive(Debug)]
+pub struct ContentTypes {
+    mime_types: HashMap<String, Mime>,
+    default_charset: Option<String>,
+    sniff: bool,
+}
+
+impl ContentTypes {
+    pub fn new(
+        mime_types: &HashMap<String, Mime>,
+        default_charset: &Option<String>,
+        sniff: Option<bool>,
+    ) -> Self {
+        ContentTypes {
+            mime_types: mime_types.clone(),
+            default_charset: default_charset.clone(),
+            sniff: sniff.unwrap_or(false),
+        }
+    }
+
+    /// Returns the content type of a file, from the configured types for its extension, the
+    /// types known for its extension, or else its content.
+    pub async fn resolve(&self, path: &Path) -> Mime {
+        let mime = match self.by_extension(path) {
+            Some(mime) => mime,
+            None if self.sniff => match sniff_file(path).await {
+                Ok(mime) => mime,
+                Err(err) => {
+                    log::warn!("Error reading file `{}`: {}", path.display(), err);
+                    mime::APPLICATION_OCTET_STREAM
+                }
+            },
+            None => mime::APPLICATION_OCTET_STREAM,
+        };
+        self.with_charset(mime)
+    }
+
+    /// Returns the content type of a file whose content is already in memory.
+    pub fn resolve_data(&self, path: &Path, data: &[u8]) -> Mime {
+        let mime = match self.by_extension(path) {
+            Some(mime) => mime,
+            None if self.sniff => sniff(&data[..data.len().min(SNIFF_LEN as usize)]),
+            None => mime::APPLICATION_OCTET_STREAM,
+        };
+        self.with_charset(mime)
+    }
+
+    fn by_extension(&self, path: &Path) -> Option<Mime> {
+        let extension = path.extension()?.to_str()?;
+        match self.mime_types.get(&extension.to_ascii_lowercase()) {
+            Some(mime) => Some(mime.clone()),
+            None => Some(mime_guess::from_ext(extension).first_or_octet_stream()),
+        }
+    }
+
+    fn with_charset(&self, mime: Mime) -> Mime {
+        match &self.default_charset {
+            Some(charset)
+                if mime.type_() == mime::TEXT && mime.get_param(mime::CHARSET).is_none() =>
+            {
+                format!("{}; charset={}", mime, charset)
+                    .parse()
+                    .unwrap_or(mime)
+            }
+            _ => mime,
+        }
+    }
+}
+
+async fn sniff_file(path: &Path) -> io::Result<Mime> {
+    let mut buf = Vec::with_capacity(SNIFF_LEN as usize);
+    fs::File::open(path)
+        .await?
+        .take(SNIFF_LEN)
+        .read_to_end(&mut buf)
+        .await?;
+    Ok(sniff(&buf))
+}
+
+/// Guesses the content type of a file from its first bytes.
+fn sniff(buf: &[u8]) -> Mime {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\0asm", "application/wasm"),
+    ];
+
+    for (signature, mime) in SIGNATURES {
+        if buf.starts_with(signature) {
+            return mime.parse().unwrap();
+        }
+    }
+
+    // Text files may be cut off in the middle of a character.
+    let text = match std::str::from_utf8(buf) {
+        Ok(text) => text,
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&buf[..err.valid_up_to()]).expect("prefix is valid utf-8")
+        }
+        Err(_) => return mime::APPLICATION_OCTET_STREAM,
+    };
+    if text
+        .chars()
+        .any(|c| c.is_control() && !c.is_ascii_whitespace())
+    {
+        return mime::APPLICATION_OCTET_STREAM;
+    }
+
+    let start = text.trim_start().to_ascii_lowercase();
+    if start.starts_with('{') || start.starts_with('[') {
+        mime::APPLICATION_JSON
+    } else if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        mime::TEXT_HTML
+    } else if start.starts_with("<svg") {
+        mime::IMAGE_SVG
+    } else if start.starts_with("<?xml") {
+        "application/xml".parse().unwrap()
+    } else {
+        mime::TEXT_PLAIN
+    }
+}
+
+#[test]
+fn test_sniff() {
+    assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0"), "image/png");
+    assert_eq!(sniff(b"  {\"id\": 1}\n"), mime::APPLICATION_JSON);
+    assert_eq!(sniff(b"<!DOCTYPE html>\n<html>"), mime::TEXT_HTML);
+    assert_eq!(sniff(b"hello \xc3"), mime::TEXT_PLAIN);
+    assert_eq!(sniff(b"\0\x01\x02"), mime::APPLICATION_OCTET_STREAM);
+}
@@ -1,22 +1,71 @@
-use std::path::{self, Path};
+use std::path::{self, Path, PathBuf};
 
-use headers::{ContentLength, ContentType, HeaderMapExt};
+use std::fs::Metadata;
+use std::io::SeekFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use futures::stream::BoxStream;
+use futures::{future, stream, Stream, StreamExt};
+use headers::{
+    AcceptRanges, ContentLength, ContentRange, ContentType, ETag, HeaderMapExt, IfModifiedSince,
+    IfNoneMatch, LastModified,
+};
 use hyper::Body;
+use mime::Mime;
 use tokio::fs;
-use tokio::io::ErrorKind;
+use tokio::io::{self, AsyncReadExt, ErrorKind};
 use urlencoding::decode;
+use uuid::Uuid;
 
+use super::content_type::ContentTypes;
+use super::listing;
+use super::policy::{Access, Policy};
+use super::range::{self, Ranges};
+use super::writable;
+use crate::config::{self, Encoding};
 use crate::method::MethodFilter;
-use crate::{config, response};
+use crate::{compress, response};
+
+/// The size of the chunks files are streamed in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The encodings of precompressed files that are looked for, from smallest to largest output.
+const PRECOMPRESSED_ENCODINGS: &[Encoding] = &[Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
 
 #[derive(Debug)]
 pub struct FileHandler {
     config: config::FileRoute,
+    options: FileOptions,
 }
 
 #[derive(Debug)]
 pub struct DirHandler {
     config: config::DirRoute,
+    options: FileOptions,
+    policy: Policy,
+}
+
+/// Settings for how files are served, shared by file, dir and archive routes.
+#[derive(Debug)]
+pub struct FileOptions {
+    pub cache_control: Option<String>,
+    pub content_types: ContentTypes,
+}
+
+/// A file to serve, along with its metadata.
+pub struct Representation {
+    pub content: Content,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    pub mime: Mime,
+}
+
+pub enum Content {
+    File(fs::File),
+    /// The content of a file held in memory, such as one read from an archive.
+    Data(Bytes),
 }
 
 pub fn default_method_filter() -> Box<dyn MethodFilter> {
@@ -25,7 +74,15 @@ pub fn default_method_filter() -> Box<dyn MethodFilter> {
 
 impl FileHandler {
     pub fn new(config: config::FileRoute) -> Self {
-        FileHandler { config }
+        let options = FileOptions {
+            cache_control: config.cache_control.clone(),
+            content_types: ContentTypes::new(
+                &config.mime_types,
+                &config.default_charset,
+                config.sniff,
+            ),
+        };
+        FileHandler { config, options }
     }
 
     pub async fn handle(
@@ -45,13 +102,40 @@ impl FileHandler {
             self.config.path.display()
         );
 
-        Ok(file_response(&self.config.path).await)
+        Ok(file_response(request.headers(), &self.config.path, None, &self.options).await)
     }
 }
 
 impl DirHandler {
-    pub fn new(config: config::DirRoute) -> Self {
-        DirHandler { config }
+    pub fn new(config: config::DirRoute) -> Result<Self> {
+        let options = FileOptions {
+            cache_control: config.cache_control.clone(),
+            content_types: ContentTypes::new(
+                &config.mime_types,
+                &config.default_charset,
+                config.sniff,
+            ),
+        };
+        let policy = Policy::new(&config)?;
+        Ok(DirHandler {
+            config,
+            options,
+            policy,
+        })
+    }
+
+    pub fn default_method_filter(&self) -> Box<dyn MethodFilter> {
+        if !self.config.writable {
+            return default_method_filter();
+        }
+
+        let mkcol = self.config.mkcol;
+        Box::new(move |method: &http::Method| {
+            matches!(
+                *method,
+                http::Method::GET | http::Method::PUT | http::Method::DELETE
+            ) || (mkcol && method == *writable::MKCOL)
+        })
     }
 
     pub async fn handle(
@@ -59,7 +143,8 @@ impl DirHandler {
         request: http::Request<Body>,
         path: &str,
     ) -> Result<http::Response<Body>, (http::Request<Body>, http::Response<Body>)> {
-        if request.method() != http::Method::GET {
+        let is_write = writable::is_write_method(&self.config, request.method());
+        if request.method() != http::Method::GET && !is_write {
             return Err((
                 request,
                 response::from_status(http::StatusCode::METHOD_NOT_ALLOWED),
@@ -82,16 +167,194 @@ impl DirHandler {
 
         match sanitize_path(&path) {
             Some(components) => {
-                let mut path = self.config.path.clone();
-                path.extend(components);
-                Ok(file_response(&path).await)
+                let is_root = components.is_empty();
+                let relative = components
+                    .iter()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let mut file_path = self.config.path.clone();
+                file_path.extend(components);
+
+                let access = self
+                    .policy
+                    .check(&relative, &file_path, is_dir(&file_path).await)
+                    .await;
+                if let Some(response) = denied_response(access, &relative) {
+                    return Ok(response);
+                }
+
+                if is_write {
+                    if !is_root && !self.policy.is_writable(&relative, &file_path).await {
+                        return Ok(response::from_status(http::StatusCode::FORBIDDEN));
+                    }
+                    return Ok(writable::write_response(
+                        request,
+                        &file_path,
+                        is_root,
+                        &self.config,
+                    )
+                    .await);
+                }
+                Ok(self
+                    .path_response(&request, &path, &relative, &file_path, is_root)
+                    .await)
             }
             None => Ok(response::from_status(http::StatusCode::NOT_FOUND)),
         }
     }
+
+    async fn path_response(
+        &self,
+        request: &http::Request<Body>,
+        path: &str,
+        relative: &str,
+        file_path: &Path,
+        is_root: bool,
+    ) -> http::Response<Body> {
+        if is_dir(file_path).await {
+            let mut index_path = None;
+            for index in &self.config.index {
+                let path = file_path.join(index);
+                let index_relative = join_relative(relative, index);
+                let access = self.policy.check(&index_relative, &path, false).await;
+                if access == Access::Allowed && is_file(&path).await {
+                    index_path = Some((path, index_relative));
+                    break;
+                }
+            }
+
+            if index_path.is_some() || self.config.listing.is_some() {
+                if let Some(response) = redirect_to_dir(request) {
+                    return response;
+                }
+            }
+            if let Some((index_path, index_relative)) = index_path {
+                return file_response(
+                    request.headers(),
+                    &index_path,
+                    Some((&self.policy, &index_relative)),
+                    &self.options,
+                )
+                .await;
+            }
+            if let Some(listing) = self.config.listing {
+                log::debug!("Listing directory `{}`", file_path.display());
+                return listing::listing_response(
+                    file_path,
+                    request.uri().path(),
+                    relative,
+                    is_root,
+                    listing,
+                    &self.policy,
+                )
+                .await;
+            }
+        } else if is_file(file_path).await {
+            return file_response(
+                request.headers(),
+                file_path,
+                Some((&self.policy, relative)),
+                &self.options,
+            )
+            .await;
+        }
+
+        if let Some(spa_fallback) = spa_fallback(self.config.spa_fallback.as_deref(), path) {
+            let fallback_path = self.config.path.join(spa_fallback);
+            let fallback_relative = spa_fallback
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            return file_response(
+                request.headers(),
+                &fallback_path,
+                Some((&self.policy, &fallback_relative)),
+                &self.options,
+            )
+            .await;
+        }
+
+        file_response(
+            request.headers(),
+            file_path,
+            Some((&self.policy, relative)),
+            &self.options,
+        )
+        .await
+    }
+}
+
+/// Returns the app shell to serve in place of a path which was not found, if there is one. Paths
+/// without an extension are assumed to be routes handled by a single-page app, rather than missing
+/// assets.
+pub fn spa_fallback<'a, T: ?Sized>(spa_fallback: Option<&'a T>, path: &str) -> Option<&'a T> {
+    let spa_fallback = spa_fallback?;
+    if Path::new(path).extension().is_some() {
+        return None;
+    }
+    log::debug!("Path `{}` not found, returning app shell", path);
+    Some(spa_fallback)
+}
+
+/// Returns the response for a request refused by a dir route's policy, if it was refused.
+fn denied_response(access: Access, relative: &str) -> Option<http::Response<Body>> {
+    match access {
+        Access::Allowed => None,
+        Access::Hidden => {
+            log::info!("Path `{}` is hidden", relative);
+            Some(response::from_status(http::StatusCode::NOT_FOUND))
+        }
+        Access::Forbidden => {
+            log::info!("Access to path `{}` is forbidden", relative);
+            Some(response::from_status(http::StatusCode::FORBIDDEN))
+        }
+    }
 }
 
-fn sanitize_path<'a>(path: &'a str) -> Option<Vec<path::Component<'a>>> {
+/// Appends a file name to a path relative to the root of a dir route.
+pub fn join_relative(relative: &str, name: &str) -> String {
+    if relative.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}/{}", relative, name)
+    }
+}
+
+pub async fn is_dir(path: &Path) -> bool {
+    fs::metadata(path)
+        .await
+        .map(|metadata| metadata.is_dir())
+        .unwrap_or(false)
+}
+
+async fn is_file(path: &Path) -> bool {
+    fs::metadata(path)
+        .await
+        .map(|metadata| metadata.is_file())
+        .unwrap_or(false)
+}
+
+/// Redirects to the path with a trailing `/` if it is missing, so that relative links in an index
+/// file or listing resolve correctly.
+pub fn redirect_to_dir(request: &http::Request<Body>) -> Option<http::Response<Body>> {
+    let request_path = request.uri().path();
+    if request_path.ends_with('/') {
+        return None;
+    }
+
+    let mut location = format!("{}/", request_path);
+    if let Some(query) = request.uri().query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    let mut response = response::from_status(http::StatusCode::MOVED_PERMANENTLY);
+    response::set_header(&mut response, http::header::LOCATION, &location);
+    Some(response)
+}
+
+pub fn sanitize_path<'a>(path: &'a str) -> Option<Vec<path::Component<'a>>> {
     let mut result = Vec::new();
 
     for component in Path::new(path).components() {
@@ -111,43 +374,376 @@ fn sanitize_path<'a>(path: &'a str) -> Option<Vec<path::Component<'a>>> {
     Some(result)
 }
 
-async fn file_response(path: &Path) -> http::Response<Body> {
+/// Returns a response for a file, or a precompressed copy of it. In a dir route, `policy` holds the
+/// route's policy and the path of the file relative to its root, and copies refused by the policy
+/// are ignored.
+async fn file_response(
+    headers: &http::HeaderMap,
+    path: &Path,
+    policy: Option<(&Policy, &str)>,
+    options: &FileOptions,
+) -> http::Response<Body> {
     log::debug!("Returning file from `{}`", path.display());
 
-    let file = match fs::read(path).await {
+    let (mut file, mut metadata) = match open_file(path).await {
+        Ok(opened) => opened,
+        Err(response) => return response,
+    };
+
+    let variants = precompressed_variants(path, policy).await;
+    let encoding = compress::negotiate(headers.get(http::header::ACCEPT_ENCODING), &variants);
+    if let Some(encoding) = encoding {
+        let variant_path = variant_path(path, encoding);
+        log::debug!("Returning precompressed file `{}`", variant_path.display());
+        match open_file(&variant_path).await {
+            Ok(opened) => {
+                file = opened.0;
+                metadata = opened.1;
+            }
+            Err(response) => return response,
+        }
+    }
+
+    let representation = Representation {
+        content: Content::File(file),
+        len: metadata.len(),
+        modified: metadata.modified().ok(),
+        mime: options.content_types.resolve(path).await,
+    };
+    let mut response = representation_response(headers, path, representation, options).await;
+    if let Some(encoding) = encoding {
+        if response.status().is_success() {
+            response::set_header(
+                &mut response,
+                http::header::CONTENT_ENCODING,
+                encoding.token(),
+            );
+        }
+    }
+    if !variants.is_empty() {
+        compress::add_vary(&mut response);
+    }
+    response
+}
+
+/// Returns a response for a file, handling conditional and range requests. `path` identifies the
+/// file in log messages.
+pub async fn representation_response(
+    headers: &http::HeaderMap,
+    path: &Path,
+    representation: Representation,
+    options: &FileOptions,
+) -> http::Response<Body> {
+    let Representation {
+        content,
+        len,
+        modified,
+        mime,
+    } = representation;
+    let last_modified = modified.map(LastModified::from);
+    let etag = file_etag(len, modified);
+
+    if !modified_since(headers, etag.as_ref(), modified) {
+        log::debug!("File `{}` not modified", path.display());
+        let mut response = response::from_status(http::StatusCode::NOT_MODIFIED);
+        add_cache_headers(&mut response, etag, last_modified, options);
+        return response;
+    }
+
+    let mut response = match range::resolve(headers, len, etag.as_ref(), last_modified.as_ref()) {
+        Ranges::Full => {
+            let mut response = http::Response::new(Body::wrap_stream(content.stream(0, len)));
+            response.headers_mut().typed_insert(ContentLength(len));
+            response.headers_mut().typed_insert(ContentType::from(mime));
+            response
+        }
+        Ranges::Partial(ranges) => match partial_response(content, len, mime, ranges).await {
+            Ok(response) => response,
+            Err(err) => {
+                log::error!("Error reading file: {}", err);
+                return response::from_status(http::StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        },
+        Ranges::Unsatisfiable => {
+            log::info!("Range not satisfiable for file `{}`", path.display());
+            let mut response = response::from_status(http::StatusCode::RANGE_NOT_SATISFIABLE);
+            response
+                .headers_mut()
+                .typed_insert(ContentRange::unsatisfied_bytes(len));
+            response
+        }
+    };
+    response.headers_mut().typed_insert(AcceptRanges::bytes());
+    add_cache_headers(&mut response, etag, last_modified, options);
+    response
+}
+
+async fn open_file(path: &Path) -> Result<(fs::File, Metadata), http::Response<Body>> {
+    let file = match fs::File::open(path).await {
         Ok(file) => file,
         Err(err) if err.kind() == ErrorKind::NotFound => {
             log::info!("File not found: `{}`", path.display());
-            return response::from_status(http::StatusCode::NOT_FOUND);
+            return Err(response::from_status(http::StatusCode::NOT_FOUND));
         }
         #[cfg(windows)]
         Err(err) if err.raw_os_error() == Some(123) => {
             log::info!("Invalid file name: `{}`", path.display());
-            return response::from_status(http::StatusCode::NOT_FOUND);
+            return Err(response::from_status(http::StatusCode::NOT_FOUND));
         }
         #[cfg(windows)]
         Err(err) if err.raw_os_error() == Some(5) && path.is_dir() => {
             log::info!("Path is a directory: `{}`", path.display());
-            return response::from_status(http::StatusCode::NOT_FOUND);
-        }
-        #[cfg(unix)]
-        Err(err) if err.raw_os_error() == Some(21) => {
-            log::info!("Path is a directory: `{}`", path.display());
-            return response::from_status(http::StatusCode::NOT_FOUND);
+            return Err(response::from_status(http::StatusCode::NOT_FOUND));
         }
         Err(err) => {
             log::error!("Error opening file: {}", err);
-            return response::from_status(http::StatusCode::INTERNAL_SERVER_ERROR);
+            return Err(response::from_status(
+                http::StatusCode::INTERNAL_SERVER_ERROR,
+            ));
         }
     };
 
-    let len = file.len();
-    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    let metadata = match file.metadata().await {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            log::error!("Error reading file metadata: {}", err);
+            return Err(response::from_status(
+                http::StatusCode::INTERNAL_SERVER_ERROR,
+            ));
+        }
+    };
+    if metadata.is_dir() {
+        log::info!("Path is a directory: `{}`", path.display());
+        return Err(response::from_status(http::StatusCode::NOT_FOUND));
+    }
+    Ok((file, metadata))
+}
 
-    let mut response = http::Response::new(Body::from(file));
-    response
-        .headers_mut()
-        .typed_insert(ContentLength(len as u64));
-    response.headers_mut().typed_insert(ContentType::from(mime));
-    response
+/// Returns the encodings of the precompressed copies of a file that exist next to it, such as
+/// `app.js.br` for `app.js`, in order of preference.
+async fn precompressed_variants(path: &Path, policy: Option<(&Policy, &str)>) -> Vec<Encoding> {
+    let mut variants = Vec::new();
+    for &encoding in PRECOMPRESSED_ENCODINGS {
+        let variant_path = variant_path(path, encoding);
+        if !is_file(&variant_path).await {
+            continue;
+        }
+        if let Some((policy, relative)) = policy {
+            let relative = format!("{}.{}", relative, encoding.extension());
+            if policy.check(&relative, &variant_path, false).await != Access::Allowed {
+                log::debug!("Ignoring precompressed file `{}`", variant_path.display());
+                continue;
+            }
+        }
+        variants.push(encoding);
+    }
+    variants
+}
+
+fn variant_path(path: &Path, encoding: Encoding) -> PathBuf {
+    let mut variant_path = path.as_os_str().to_owned();
+    variant_path.push(".");
+    variant_path.push(encoding.extension());
+    PathBuf::from(variant_path)
+}
+
+/// Builds a strong entity tag from a file's size and modification time.
+fn file_etag(len: u64, modified: Option<SystemTime>) -> Option<ETag> {
+    let modified = modified?.duration_since(UNIX_EPOCH).ok()?;
+    format!(
+        "\"{:x}-{:x}.{:x}\"",
+        len,
+        modified.as_secs(),
+        modified.subsec_nanos()
+    )
+    .parse()
+    .ok()
+}
+
+/// Evaluates `If-None-Match`, or `If-Modified-Since` if it is absent. Returns false if the client's
+/// copy is current.
+fn modified_since(
+    headers: &http::HeaderMap,
+    etag: Option<&ETag>,
+    modified: Option<SystemTime>,
+) -> bool {
+    if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+        return match etag {
+            Some(etag) => if_none_match.precondition_passes(etag),
+            None => true,
+        };
+    }
+    match (headers.typed_get::<IfModifiedSince>(), modified) {
+        (Some(if_modified_since), Some(modified)) => if_modified_since.is_modified(modified),
+        _ => true,
+    }
+}
+
+fn add_cache_headers(
+    response: &mut http::Response<Body>,
+    etag: Option<ETag>,
+    last_modified: Option<LastModified>,
+    options: &FileOptions,
+) {
+    if let Some(etag) = etag {
+        response.headers_mut().typed_insert(etag);
+    }
+    if let Some(last_modified) = last_modified {
+        response.headers_mut().typed_insert(last_modified);
+    }
+    if let Some(cache_control) = &options.cache_control {
+        response::set_header(response, http::header::CACHE_CONTROL, cache_control);
+    }
+}
+
+/// Returns a `206 Partial Content` response containing the requested ranges of a file. Multiple
+/// ranges are sent as a `multipart/byteranges` body.
+async fn partial_response(
+    content: Content,
+    len: u64,
+    mime: Mime,
+    ranges: Vec<(u64, u64)>,
+) -> io::Result<http::Response<Body>> {
+    let mut response = if let [(start, end)] = ranges[..] {
+        let mut response =
+            http::Response::new(Body::wrap_stream(content.stream(start, end - start + 1)));
+        response
+            .headers_mut()
+            .typed_insert(ContentLength(end - start + 1));
+        response
+            .headers_mut()
+            .typed_insert(ContentRange::bytes(start..=end, len).expect("range is within the file"));
+        response.headers_mut().typed_insert(ContentType::from(mime));
+        response
+    } else {
+        let boundary = Uuid::new_v4().to_simple().to_string();
+        let mut content_length = 0;
+        let mut parts = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            let header = Bytes::from(format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                boundary, mime, start, end, len
+            ));
+            content_length += header.len() as u64 + (end - start + 1);
+
+            // The clones share a cursor, but each part seeks before it reads and the parts are
+            // read one after another.
+            let part = content.try_clone().await?.stream(start, end - start + 1);
+            parts.push(stream::once(future::ok(header)).chain(part));
+        }
+        let trailer = Bytes::from(format!("\r\n--{}--\r\n", boundary));
+        content_length += trailer.len() as u64;
+
+        let body = stream::iter(parts)
+            .flatten()
+            .chain(stream::once(future::ok(trailer)));
+        let mut response = http::Response::new(Body::wrap_stream(body));
+        response
+            .headers_mut()
+            .typed_insert(ContentLength(content_length));
+        response::set_header(
+            &mut response,
+            http::header::CONTENT_TYPE,
+            &format!("multipart/byteranges; boundary={}", boundary),
+        );
+        response
+    };
+
+    *response.status_mut() = http::StatusCode::PARTIAL_CONTENT;
+    Ok(response)
+}
+
+impl Content {
+    async fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Content::File(file) => Ok(Content::File(file.try_clone().await?)),
+            Content::Data(data) => Ok(Content::Data(data.clone())),
+        }
+    }
+
+    /// Streams `len` bytes of the content starting at `start`.
+    fn stream(self, start: u64, len: u64) -> BoxStream<'static, io::Result<Bytes>> {
+        match self {
+            Content::File(file) => file_stream(file, start, len).boxed(),
+            Content::Data(data) => {
+                let part = data.slice(start as usize..(start + len) as usize);
+                stream::once(future::ok(part)).boxed()
+            }
+        }
+    }
+}
+
+/// Streams `len` bytes of `file` starting at `start`, in chunks.
+fn file_stream(file: fs::File, start: u64, len: u64) -> impl Stream<Item = io::Result<Bytes>> {
+    stream::unfold(
+        (file, Some(start), len),
+        |(mut file, seek, remaining)| async move {
+            if remaining == 0 {
+                return None;
+            }
+            if let Some(start) = seek {
+                if let Err(err) = file.seek(SeekFrom::Start(start)).await {
+                    return Some((Err(err), (file, None, 0)));
+                }
+            }
+
+            let mut buf = BytesMut::with_capacity((remaining as usize).min(CHUNK_SIZE));
+            match (&mut file).take(remaining).read_buf(&mut buf).await {
+                Ok(0) => Some((
+                    Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "file was truncated while it was being read",
+                    )),
+                    (file, None, 0),
+                )),
+                Ok(read) => Some((Ok(buf.freeze()), (file, None, remaining - read as u64))),
+                Err(err) => Some((Err(err), (file, None, 0))),
+            }
+        },
+    )
+}
+
+#[tokio::test]
+async fn test_index_and_spa_fallback() {
+    async fn get(handler: &DirHandler, path: &str) -> (http::StatusCode, Bytes) {
+        let request = http::Request::get(path).body(Body::empty()).unwrap();
+        let response = handler.handle(request, path).await.unwrap();
+        let status = response.status();
+        (status, hyper::body::to_bytes(response).await.unwrap())
+    }
+
+    let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    std::fs::create_dir_all(root.join("docs")).unwrap();
+    std::fs::create_dir_all(root.join("empty")).unwrap();
+    std::fs::write(root.join("index.html"), "<h1>App</h1>").unwrap();
+    std::fs::write(root.join("docs/index.htm"), "<h1>Docs</h1>").unwrap();
+    std::fs::write(root.join("docs/index.html"), "<h1>Other</h1>").unwrap();
+    let config: config::DirRoute = serde_yaml::from_str(&format!(
+        "{{ path: '{}', index: [index.htm, index.html], spa-fallback: index.html }}",
+        root.display()
+    ))
+    .unwrap();
+    let handler = DirHandler::new(config).unwrap();
+
+    // The first index file which exists is served, after redirecting to the directory's path.
+    let (status, body) = get(&handler, "/").await;
+    assert_eq!(status, http::StatusCode::OK);
+    assert_eq!(body, "<h1>App</h1>");
+    let (status, body) = get(&handler, "/docs/").await;
+    assert_eq!(status, http::StatusCode::OK);
+    assert_eq!(body, "<h1>Docs</h1>");
+    let (status, _) = get(&handler, "/docs").await;
+    assert_eq!(status, http::StatusCode::MOVED_PERMANENTLY);
+
+    // Missing paths without an extension get the app shell, but missing assets don't.
+    let (status, body) = get(&handler, "/posts/1").await;
+    assert_eq!(status, http::StatusCode::OK);
+    assert_eq!(body, "<h1>App</h1>");
+    let (status, body) = get(&handler, "/empty/").await;
+    assert_eq!(status, http::StatusCode::OK);
+    assert_eq!(body, "<h1>App</h1>");
+    let (status, _) = get(&handler, "/assets/missing.js").await;
+    assert_eq!(status, http::StatusCode::NOT_FOUND);
+
+    std::fs::remove_dir_all(&root).unwrap();
 }
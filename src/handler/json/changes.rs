@@ -0,0 +1,257 @@
+use std::convert::Infallible;
+use std::sync::atomic::Ordering;
+
+use futures::{stream, SinkExt, StreamExt};
+use hyper::Body;
+use json_patch::Patch;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::broadcast::{self, RecvError};
+use tokio_tungstenite::tungstenite::handshake::server::create_response;
+use tokio_tungstenite::tungstenite::protocol::{Message, Role};
+use tokio_tungstenite::WebSocketStream;
+
+use super::State;
+use crate::response;
+
+/// The path, relative to the route, at which clients can subscribe to changes.
+pub const CHANGES_PATH: &str = "/_changes";
+
+/// The number of changes buffered for each subscriber before it is considered lagging.
+pub const CHANGES_CAPACITY: usize = 256;
+
+/// A modification to a json route's data, as a JSON Patch relative to the root of the route.
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub version: u64,
+    pub patch: Value,
+}
+
+impl State {
+    /// Notifies subscribers of a change. This should be called while the write lock on the value
+    /// is held, so that changes are published in the order they were applied.
+    pub(super) fn publish(&self, patch: Value) {
+        let version = self.version.fetch_add(1, Ordering::SeqCst) + 1;
+        // An error just means there are currently no subscribers.
+        let _ = self.changes.send(Change { version, patch });
+    }
+}
+
+/// Builds a single operation patch.
+pub fn operation(op: &str, path: &str, value: Option<&Value>) -> Value {
+    let mut operation = serde_json::json!({ "op": op, "path": path });
+    if let Some(value) = value {
+        operation["value"] = value.clone();
+    }
+    Value::Array(vec![operation])
+}
+
+/// Rewrites a patch applied to the value at `prefix` to be relative to the root of the route.
+pub fn prefix_patch(patch: &Patch, prefix: &str) -> Value {
+    let mut patch = serde_json::to_value(patch).expect("patch is valid JSON");
+    if let Value::Array(operations) = &mut patch {
+        for operation in operations {
+            for key in &["path", "from"] {
+                if let Some(Value::String(path)) = operation.get_mut(*key) {
+                    path.insert_str(0, prefix);
+                }
+            }
+        }
+    }
+    patch
+}
+
+/// Subscribes to changes, using a WebSocket if the client requested an upgrade, or server-sent
+/// events otherwise.
+pub fn subscribe(state: &State, request: http::Request<Body>) -> http::Response<Body> {
+    let receiver = state.changes.subscribe();
+    let version = state.version.load(Ordering::SeqCst);
+
+    if request.headers().contains_key(http::header::UPGRADE) {
+        websocket(request, receiver)
+    } else {
+        event_stream(receiver, version)
+    }
+}
+
+fn event_stream(receiver: broadcast::Receiver<Change>, version: u64) -> http::Response<Body> {
+    let initial = stream::once(async move {
+        Ok::<_, Infallible>(format!("event: version\ndata: {}\n\n", version))
+    });
+    let changes = stream::unfold(receiver, |mut receiver| async move {
+        let event = match receiver.recv().await {
+            Ok(change) => format!(
+                "id: {}\nevent: change\ndata: {}\n\n",
+                change.version,
+                serde_json::to_string(&change).expect("writing value to a string should not fail")
+            ),
+            Err(RecvError::Lagged(skipped)) => format!("event: lagged\ndata: {}\n\n", skipped),
+            Err(RecvError::Closed) => return None,
+        };
+        Some((Ok(event), receiver))
+    });
+
+    let mut response = http::Response::new(Body::wrap_stream(initial.chain(changes)));
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_static("text/event-stream"),
+    );
+    response.headers_mut().insert(
+        http::header::CACHE_CONTROL,
+        http::HeaderValue::from_static("no-cache"),
+    );
+    response
+}
+
+fn websocket(
+    request: http::Request<Body>,
+    receiver: broadcast::Receiver<Change>,
+) -> http::Response<Body> {
+    let (parts, body) = request.into_parts();
+    let handshake = match create_response(&http::Request::from_parts(parts, ())) {
+        Ok(handshake) => handshake,
+        Err(err) => {
+            log::info!("Invalid WebSocket handshake: {}", err);
+            return response::from_status(http::StatusCode::BAD_REQUEST);
+        }
+    };
+
+    tokio::spawn(async move {
+        match body.on_upgrade().await {
+            Ok(upgraded) => {
+                let socket = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                forward_changes(socket, receiver).await;
+            }
+            Err(err) => log::error!("Error upgrading connection to WebSocket: {}", err),
+        }
+    });
+
+    let (parts, ()) = handshake.into_parts();
+    http::Response::from_parts(parts, Body::empty())
+}
+
+async fn forward_changes<S>(socket: WebSocketStream<S>, mut receiver: broadcast::Receiver<Change>)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let (mut sink, mut stream) = socket.split();
+
+    loop {
+        tokio::select! {
+            change = receiver.recv() => {
+                let message = match change {
+                    Ok(change) => serde_json::to_string(&change)
+                        .expect("writing value to a string should not fail"),
+                    Err(RecvError::Lagged(skipped)) => {
+                        serde_json::json!({ "lagged": skipped }).to_string()
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if let Err(err) = sink.send(Message::Text(message)).await {
+                    log::info!("Error sending change to WebSocket: {}", err);
+                    break;
+                }
+            }
+            message = stream.next() => match message {
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => (),
+                Some(Err(err)) => {
+                    log::info!("Error reading from WebSocket: {}", err);
+                    break;
+                }
+            },
+        }
+    }
+}
+
+#[test]
+fn test_prefix_patch() {
+    let patch: Patch = serde_json::from_value(serde_json::json!([
+        { "op": "replace", "path": "/title", "value": "x" },
+        { "op": "move", "from": "/a", "path": "/b" },
+    ]))
+    .unwrap();
+
+    assert_eq!(
+        prefix_patch(&patch, "/posts/1"),
+        serde_json::json!([
+            { "op": "replace", "path": "/posts/1/title", "value": "x" },
+            { "op": "move", "from": "/posts/1/a", "path": "/posts/1/b" },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn test_event_stream() {
+    use std::sync::Arc;
+
+    use crate::config::Config;
+    use crate::route::Router;
+
+    async fn next_event(events: &mut Body) -> String {
+        let chunk = events.next().await.unwrap().unwrap();
+        String::from_utf8(chunk.to_vec()).unwrap()
+    }
+
+    let path = std::env::temp_dir().join(format!("{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(
+        &path,
+        r#"{
+            "posts": [{ "id": 3, "title": "a" }, { "id": 7, "title": "b" }],
+            "comments": [{ "id": 1, "postId": 3 }, { "id": 5, "postId": 7 }]
+        }"#,
+    )
+    .unwrap();
+    let config: Config = serde_yaml::from_str(&format!(
+        "{{ routes: [{{ route: '/**', kind: json, path: '{}', relations: [{{ from: comments.postId, to: posts.id, cascade: true }}] }}] }}",
+        path.display()
+    ))
+    .unwrap();
+    let router = Arc::new(Router::new(config).await.unwrap());
+
+    let request = http::Request::get(CHANGES_PATH)
+        .body(Body::empty())
+        .unwrap();
+    let response = router.clone().try_handle(request).await;
+    assert_eq!(
+        response.headers()[http::header::CONTENT_TYPE],
+        "text/event-stream"
+    );
+    let mut events = response.into_body();
+    assert_eq!(next_event(&mut events).await, "event: version\ndata: 0\n\n");
+
+    // Items are addressed by id in requests, but by index in the published patches.
+    let request = http::Request::patch("/posts/7")
+        .header(http::header::CONTENT_TYPE, "application/merge-patch+json")
+        .body(Body::from(r#"{ "title": "c" }"#))
+        .unwrap();
+    let response = router.clone().try_handle(request).await;
+    assert_eq!(response.status(), http::StatusCode::OK);
+    let event = next_event(&mut events).await;
+    assert!(event.starts_with("id: 1\nevent: change\ndata: "));
+    let change: Value = serde_json::from_str(event[26..].trim_end()).unwrap();
+    assert_eq!(
+        change,
+        serde_json::json!({
+            "version": 1,
+            "patch": [{ "op": "replace", "path": "/posts/1", "value": { "id": 7, "title": "c" } }],
+        })
+    );
+
+    let request = http::Request::delete("/posts/7")
+        .body(Body::empty())
+        .unwrap();
+    let response = router.try_handle(request).await;
+    assert_eq!(response.status(), http::StatusCode::NO_CONTENT);
+    let event = next_event(&mut events).await;
+    let change: Value = serde_json::from_str(event[26..].trim_end()).unwrap();
+    assert_eq!(
+        change["patch"],
+        serde_json::json!([
+            { "op": "remove", "path": "/posts/1" },
+            { "op": "remove", "path": "/comments/1" },
+        ])
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,60 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+use headers::{ETag, HeaderMapExt, IfMatch, IfNoneMatch};
+use hyper::Body;
+use serde::Serialize;
+
+use crate::response;
+
+/// Computes a strong entity tag from the serialized JSON representation of a value.
+pub fn compute<T: Serialize>(value: &T) -> ETag {
+    let bytes = serde_json::to_vec(value).expect("writing value to a string should not fail");
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&bytes);
+    format!("\"{:016x}\"", hasher.finish())
+        .parse()
+        .expect("generated etag is valid")
+}
+
+/// Returns a JSON response with an `ETag` header.
+pub fn json<T: Serialize>(value: &T) -> http::Response<Body> {
+    let mut response = response::json(value);
+    response.headers_mut().typed_insert(compute(value));
+    response
+}
+
+/// Returns a JSON response with an `ETag` header, or `304 Not Modified` if the request's
+/// `If-None-Match` header matches the current entity tag.
+pub fn conditional_json<T: Serialize>(
+    headers: &http::HeaderMap,
+    value: &T,
+) -> http::Response<Body> {
+    let etag = compute(value);
+
+    let mut response = match headers.typed_get::<IfNoneMatch>() {
+        Some(if_none_match) if !if_none_match.precondition_passes(&etag) => {
+            response::from_status(http::StatusCode::NOT_MODIFIED)
+        }
+        _ => response::json(value),
+    };
+    response.headers_mut().typed_insert(etag);
+    response
+}
+
+/// Checks the request's `If-Match` header, if any, against the current value.
+pub fn if_match_passes<T: Serialize>(if_match: &Option<IfMatch>, value: &T) -> bool {
+    match if_match {
+        Some(if_match) => if_match.precondition_passes(&compute(value)),
+        None => true,
+    }
+}
+
+#[test]
+fn test_compute() {
+    let a = serde_json::json!({ "a": 1 });
+    let b = serde_json::json!({ "a": 2 });
+
+    assert_eq!(compute(&a), compute(&a.clone()));
+    assert_ne!(compute(&a), compute(&b));
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use tokio::fs;
+use tokio::io;
+
+use super::format;
+use crate::config::{self, JsonFormat};
+
+/// The modification times and sizes of the files backing a json route, used to detect changes
+/// made by other processes.
+pub type Versions = BTreeMap<PathBuf, FileVersion>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FileVersion {
+    modified: SystemTime,
+    len: u64,
+}
+
+/// Reads the data for a json route. If its path is a directory, each file in it with the
+/// extension for the route's format becomes a top-level key named after the file.
+pub async fn read(config: &config::JsonRoute) -> Result<Value> {
+    let value = if config.is_dir() {
+        let mut object = Map::new();
+        for path in data_files(config).await? {
+            let key = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .with_context(|| format!("invalid file name `{}`", path.display()))?;
+            object.insert(key.to_owned(), read_file(config.format(), &path).await?);
+        }
+        Value::Object(object)
+    } else {
+        read_file(config.format(), &config.path).await?
+    };
+
+    format::check(config.format(), &value, config.is_dir()).with_context(|| {
+        format!(
+            "data in `{}` can't be written as {}",
+            config.path.display(),
+            config.format()
+        )
+    })?;
+    Ok(value)
+}
+
+async fn read_file(format: JsonFormat, path: &Path) -> Result<Value> {
+    let buf = fs::read(path)
+        .await
+        .with_context(|| format!("failed to read from file `{}`", path.display()))?;
+    if format::has_comments(format, &buf) {
+        log::warn!(
+            "Comments in file `{}` will be lost when its data is modified",
+            path.display()
+        );
+    }
+    format::parse(format, &buf)
+        .with_context(|| format!("failed to read {} from file `{}`", format, path.display()))
+}
+
+/// Returns the versions of the files backing a json route.
+pub async fn versions(config: &config::JsonRoute) -> io::Result<Versions> {
+    let paths = if config.is_dir() {
+        data_files(config).await?
+    } else {
+        vec![config.path.clone()]
+    };
+
+    let mut versions = Versions::new();
+    for path in paths {
+        let metadata = fs::metadata(&path).await?;
+        let version = FileVersion {
+            modified: metadata.modified()?,
+            len: metadata.len(),
+        };
+        versions.insert(path, version);
+    }
+    Ok(versions)
+}
+
+/// Returns the path of the file in a json route's directory which stores the top-level key `key`,
+/// or `None` if the key can't be used as a file name.
+pub fn key_path(config: &config::JsonRoute, key: &str) -> Option<PathBuf> {
+    if !is_valid_key(key) {
+        return None;
+    }
+    Some(
+        config
+            .path
+            .join(format!("{}.{}", key, extension(config.format()))),
+    )
+}
+
+/// Returns whether a top-level key can be stored in a file named after it.
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && !key.starts_with('.') && !key.contains(&['/', '\\'][..])
+}
+
+async fn data_files(config: &config::JsonRoute) -> io::Result<Vec<PathBuf>> {
+    let extension = extension(config.format());
+    let mut entries = fs::read_dir(&config.path).await?;
+
+    let mut paths = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let is_hidden = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| !name.starts_with('.'))
+            .is_none();
+        if !is_hidden
+            && path.extension().and_then(|ext| ext.to_str()) == Some(extension)
+            && path.is_file()
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn extension(format: JsonFormat) -> &'static str {
+    match format {
+        JsonFormat::Json => "json",
+        JsonFormat::Yaml => "yaml",
+        JsonFormat::Toml => "toml",
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use super::files;
+use crate::config::JsonFormat;
+
+pub fn parse(format: JsonFormat, buf: &[u8]) -> Result<Value> {
+    Ok(match format {
+        JsonFormat::Json => serde_json::from_slice(buf)?,
+        JsonFormat::Yaml => serde_yaml::from_slice(buf)?,
+        JsonFormat::Toml => toml::from_slice(buf)?,
+    })
+}
+
+/// Checks that data can be written in a format. If `is_dir` is set, the data must be an object
+/// whose keys can be used as file names, and each of its values is a document; otherwise the data
+/// is a single document. TOML documents must be tables.
+pub fn check(format: JsonFormat, value: &Value, is_dir: bool) -> Result<()> {
+    let documents: Vec<&Value> = if is_dir {
+        let object = match value {
+            Value::Object(object) => object,
+            _ => bail!("data stored in a directory must be an object"),
+        };
+        if let Some(key) = object.keys().find(|key| !files::is_valid_key(key)) {
+            bail!("key `{}` can't be used as a file name", key);
+        }
+        object.values().collect()
+    } else {
+        vec![value]
+    };
+
+    if format != JsonFormat::Toml {
+        return Ok(());
+    }
+    for document in documents {
+        if !toml::Value::try_from(document)?.is_table() {
+            bail!("a TOML document must be a table");
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether a YAML or TOML file has comments, which are lost when it is rewritten.
+pub fn has_comments(format: JsonFormat, buf: &[u8]) -> bool {
+    format != JsonFormat::Json
+        && String::from_utf8_lossy(buf)
+            .lines()
+            .any(|line| line.trim_start().starts_with('#'))
+}
+
+pub fn write(format: JsonFormat, value: &Value, pretty: bool, buf: &mut Vec<u8>) -> Result<()> {
+    match format {
+        JsonFormat::Json if pretty => serde_json::to_writer_pretty(buf, value)?,
+        JsonFormat::Json => serde_json::to_writer(buf, value)?,
+        JsonFormat::Yaml => serde_yaml::to_writer(buf, value)?,
+        JsonFormat::Toml => {
+            // Converting to a TOML value first ensures tables are written after plain values.
+            let value = toml::Value::try_from(value)?;
+            let string = if pretty {
+                toml::to_string_pretty(&value)?
+            } else {
+                toml::to_string(&value)?
+            };
+            buf.extend_from_slice(string.as_bytes());
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for JsonFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonFormat::Json => f.write_str("JSON"),
+            JsonFormat::Yaml => f.write_str("YAML"),
+            JsonFormat::Toml => f.write_str("TOML"),
+        }
+    }
+}
+
+#[test]
+fn test_check() {
+    let object = serde_json::json!({ "title": "fixture" });
+    assert!(check(JsonFormat::Toml, &object, false).is_ok());
+    assert!(check(JsonFormat::Toml, &object, true).is_err());
+    assert!(check(JsonFormat::Toml, &serde_json::json!([1, 2]), false).is_err());
+    assert!(check(JsonFormat::Toml, &serde_json::Value::Null, false).is_err());
+    assert!(check(JsonFormat::Yaml, &serde_json::Value::Null, false).is_ok());
+
+    assert!(check(JsonFormat::Json, &serde_json::json!({ "posts": [] }), true).is_ok());
+    assert!(check(JsonFormat::Json, &serde_json::json!([1, 2]), true).is_err());
+    assert!(check(JsonFormat::Json, &serde_json::json!({ "a/b": 1 }), true).is_err());
+    assert!(check(JsonFormat::Json, &serde_json::json!({ ".x": 2 }), true).is_err());
+}
+
+#[test]
+fn test_toml_round_trip() {
+    let value = serde_json::json!({
+        "posts": [{ "id": 1, "title": "a" }],
+        "title": "fixture",
+    });
+
+    let mut buf = Vec::new();
+    write(JsonFormat::Toml, &value, false, &mut buf).unwrap();
+    assert_eq!(parse(JsonFormat::Toml, &buf).unwrap(), value);
+}
@@ -0,0 +1,714 @@
+mod changes;
+mod etag;
+mod files;
+mod format;
+mod pointer;
+mod query;
+mod registry;
+mod relations;
+mod session;
+mod sync;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use bytes::buf::BufExt;
+use headers::{ContentType, HeaderMapExt, IfMatch};
+use hyper::body::{self, Body};
+use json_patch::{Patch, PatchError};
+use mime::Mime;
+use serde::de::DeserializeOwned;
+use tokio::sync::{broadcast, Notify, RwLock};
+use urlencoding::{decode, encode};
+
+pub use self::registry::JsonRegistry;
+
+use self::changes::{Change, CHANGES_CAPACITY, CHANGES_PATH};
+use self::query::Query;
+use self::session::Data;
+use self::sync::Sync;
+use crate::method::MethodFilter;
+use crate::schema::{self, Schema};
+use crate::{config, response};
+
+#[derive(Debug)]
+pub struct JsonHandler {
+    state: Arc<State>,
+    registry: Arc<JsonRegistry>,
+    id: config::JsonId,
+    relations: Vec<config::JsonRelation>,
+}
+
+#[derive(Debug)]
+struct State {
+    name: Option<String>,
+    value: RwLock<serde_json::Value>,
+    initial: serde_json::Value,
+    snapshots: Mutex<BTreeMap<String, serde_json::Value>>,
+    dirty: Notify,
+    version: AtomicU64,
+    changes: broadcast::Sender<Change>,
+    sessions: Mutex<HashMap<String, Arc<RwLock<serde_json::Value>>>>,
+    schema: Option<Schema>,
+    format: config::JsonFormat,
+    is_dir: bool,
+}
+
+#[derive(Debug)]
+enum PatchRequest {
+    Json(Patch),
+    Merge(serde_json::Value),
+}
+
+pub fn default_method_filter() -> Box<dyn MethodFilter> {
+    Box::new(|method: &http::Method| {
+        matches!(
+            *method,
+            http::Method::GET
+                | http::Method::PATCH
+                | http::Method::PUT
+                | http::Method::POST
+                | http::Method::DELETE
+        )
+    })
+}
+
+impl JsonHandler {
+    pub async fn new(config: config::JsonRoute, registry: Arc<JsonRegistry>) -> Result<Self> {
+        let value = files::read(&config).await?;
+
+        let schema = config
+            .schema
+            .as_ref()
+            .map(|path| Schema::load(path))
+            .transpose()?;
+        if let Some(schema) = &schema {
+            if let Err(violations) = schema.validate(&value) {
+                log::warn!(
+                    "Data in file `{}` does not conform to its schema: {:?}",
+                    config.path.display(),
+                    violations
+                );
+            }
+        }
+
+        let state = Arc::new(State {
+            name: config.name.clone(),
+            initial: value.clone(),
+            value: RwLock::new(value),
+            snapshots: Mutex::new(BTreeMap::new()),
+            dirty: Notify::new(),
+            version: AtomicU64::new(0),
+            changes: broadcast::channel(CHANGES_CAPACITY).0,
+            sessions: Mutex::new(HashMap::new()),
+            schema,
+            format: config.format(),
+            is_dir: config.is_dir(),
+        });
+        registry.register(state.clone());
+
+        let id = config.id.clone();
+        let relations = config.relations.clone();
+        let sync = Sync::new(state.clone(), config).await;
+        tokio::spawn(sync.run());
+
+        Ok(JsonHandler {
+            state,
+            registry,
+            id,
+            relations,
+        })
+    }
+
+    pub async fn handle(
+        &self,
+        request: http::Request<Body>,
+        path: &str,
+    ) -> Result<http::Response<Body>, (http::Request<Body>, http::Response<Body>)> {
+        let path = match decode(path) {
+            Ok(path) => path,
+            Err(err) => {
+                log::info!("Invalid path `{}`: {}", path, err);
+                return Ok(response::from_status(http::StatusCode::BAD_REQUEST));
+            }
+        };
+
+        if path == CHANGES_PATH && request.method() == http::Method::GET {
+            return Ok(changes::subscribe(&self.state, request));
+        }
+
+        match *request.method() {
+            http::Method::GET => Ok(self.handle_get(request, &path).await),
+            http::Method::PATCH => Ok(self.handle_patch(request, &path).await),
+            http::Method::PUT => Ok(self.handle_put(request, &path).await),
+            http::Method::POST => Ok(self.handle_post(request, &path).await),
+            http::Method::DELETE => Ok(self.handle_delete(request, &path).await),
+            _ => Err((
+                request,
+                response::from_status(http::StatusCode::METHOD_NOT_ALLOWED),
+            )),
+        }
+    }
+
+    pub async fn handle_get(
+        &self,
+        request: http::Request<Body>,
+        path: &str,
+    ) -> http::Response<Body> {
+        let data = self.data(&request, false).await;
+        let value = data.value().read().await;
+        let subvalue = match pointer::get(&value, path, &self.id) {
+            Some(subvalue) => subvalue,
+            None => {
+                log::info!("Pointer `{}` did not match JSON", path);
+                return response::from_status(http::StatusCode::NOT_FOUND);
+            }
+        };
+
+        let query = match request.uri().query().map(Query::parse).transpose() {
+            Ok(query) => query,
+            Err(err) => {
+                log::info!("Invalid query: {}", err);
+                return response::from_status(http::StatusCode::BAD_REQUEST);
+            }
+        };
+
+        // The values selected by a JSONPath expression are filtered, sorted and paginated like
+        // the elements of an array.
+        let selected;
+        let subvalue = match &query {
+            Some(Query {
+                expression: Some(expression),
+                includes,
+                ..
+            }) => {
+                if !includes.is_empty() {
+                    log::info!("Invalid query: `query` can't be used with `_expand` or `_embed`");
+                    return response::from_status(http::StatusCode::BAD_REQUEST);
+                }
+                match jsonpath_lib::select(subvalue, expression) {
+                    Ok(matches) => {
+                        selected = serde_json::Value::Array(matches.into_iter().cloned().collect());
+                        &selected
+                    }
+                    Err(err) => {
+                        log::info!("Invalid JSONPath expression `{}`: {:?}", expression, err);
+                        return response::from_status(http::StatusCode::BAD_REQUEST);
+                    }
+                }
+            }
+            _ => subvalue,
+        };
+
+        match (subvalue, query) {
+            (serde_json::Value::Array(array), query) => {
+                let query = query.unwrap_or_default();
+                let listing = query.apply(array, request.uri().path());
+                let mut response = if query.includes.is_empty() {
+                    etag::conditional_json(request.headers(), &listing.items)
+                } else {
+                    let items: Result<Vec<_>, _> = listing
+                        .items
+                        .iter()
+                        .map(|item| self.include(&value, path, false, &query, item))
+                        .collect();
+                    match items {
+                        Ok(items) => etag::conditional_json(request.headers(), &items),
+                        Err(err) => {
+                            log::info!("Invalid query: {}", err);
+                            return response::from_status(http::StatusCode::BAD_REQUEST);
+                        }
+                    }
+                };
+                response::set_header(
+                    &mut response,
+                    http::header::HeaderName::from_static("x-total-count"),
+                    &listing.total.to_string(),
+                );
+                if let Some(link) = listing.link {
+                    response::set_header(&mut response, http::header::LINK, &link);
+                }
+                response
+            }
+            (subvalue, Some(query)) if !query.includes.is_empty() => {
+                match self.include(&value, path, true, &query, subvalue) {
+                    Ok(item) => etag::conditional_json(request.headers(), &item),
+                    Err(err) => {
+                        log::info!("Invalid query: {}", err);
+                        response::from_status(http::StatusCode::BAD_REQUEST)
+                    }
+                }
+            }
+            (subvalue, _) => etag::conditional_json(request.headers(), subvalue),
+        }
+    }
+
+    /// Includes the related items requested with `_expand` and `_embed` in an item, which must be
+    /// addressed by `path` or, if `is_item` is false, be an element of the collection it addresses.
+    fn include(
+        &self,
+        root: &serde_json::Value,
+        path: &str,
+        is_item: bool,
+        query: &Query,
+        item: &serde_json::Value,
+    ) -> Result<serde_json::Value, String> {
+        match relations::collection(path) {
+            Some((collection, item_path)) if item_path == is_item => {
+                query
+                    .includes
+                    .apply(root, &collection, item, &self.relations)
+            }
+            _ => Err(format!(
+                "`_expand` and `_embed` are only supported on top-level collections, not `{}`",
+                path
+            )),
+        }
+    }
+
+    pub async fn handle_patch(
+        &self,
+        request: http::Request<Body>,
+        path: &str,
+    ) -> http::Response<Body> {
+        let data = self.data(&request, true).await;
+        let if_match = request.headers().typed_get::<IfMatch>();
+        let patch = match patch_request(request).await {
+            Ok(patch) => patch,
+            Err(response) => return response,
+        };
+
+        {
+            let mut value = data.value().write().await;
+            let original = self.needs_original().then(|| value.clone());
+
+            let (response, change) = {
+                let (subvalue, change_path) =
+                    match pointer::get_mut_indexed(&mut value, path, &self.id) {
+                        Some(resolved) => resolved,
+                        None => {
+                            log::info!("Pointer `{}` did not match JSON", path);
+                            return response::from_status(http::StatusCode::NOT_FOUND);
+                        }
+                    };
+
+                if !etag::if_match_passes(&if_match, subvalue) {
+                    log::info!("Precondition failed for pointer `{}`", path);
+                    return response::from_status(http::StatusCode::PRECONDITION_FAILED);
+                }
+
+                let change = match patch {
+                    PatchRequest::Json(patch) => {
+                        if let Err(err) = json_patch::patch(subvalue, &patch) {
+                            log::info!("Failed to apply patch: {}", err);
+                            return match err {
+                                PatchError::TestFailed => {
+                                    response::from_status(http::StatusCode::CONFLICT)
+                                }
+                                PatchError::InvalidPointer => {
+                                    response::from_status(http::StatusCode::NOT_FOUND)
+                                }
+                            };
+                        }
+                        changes::prefix_patch(&patch, &change_path)
+                    }
+                    PatchRequest::Merge(patch) => {
+                        json_patch::merge(subvalue, &patch);
+                        changes::operation("replace", &change_path, Some(subvalue))
+                    }
+                };
+
+                (etag::json(subvalue), change)
+            };
+
+            if let Some(response) = self.validate(&mut value, original) {
+                return response;
+            }
+            data.commit(change);
+            response
+        }
+    }
+
+    pub async fn handle_put(
+        &self,
+        request: http::Request<Body>,
+        path: &str,
+    ) -> http::Response<Body> {
+        let data = self.data(&request, true).await;
+        let if_match = request.headers().typed_get::<IfMatch>();
+        let new_value = match json_request::<serde_json::Value>(request).await {
+            Ok(new_value) => new_value,
+            Err(response) => return response,
+        };
+
+        {
+            let mut value = data.value().write().await;
+            let original = self.needs_original().then(|| value.clone());
+
+            let (response, change) = {
+                let (subvalue, change_path) =
+                    match pointer::get_mut_indexed(&mut value, path, &self.id) {
+                        Some(resolved) => resolved,
+                        None => {
+                            log::info!("Pointer `{}` did not match JSON", path);
+                            return response::from_status(http::StatusCode::NOT_FOUND);
+                        }
+                    };
+
+                if !etag::if_match_passes(&if_match, subvalue) {
+                    log::info!("Precondition failed for pointer `{}`", path);
+                    return response::from_status(http::StatusCode::PRECONDITION_FAILED);
+                }
+
+                *subvalue = new_value;
+                (
+                    etag::json(subvalue),
+                    changes::operation("replace", &change_path, Some(subvalue)),
+                )
+            };
+
+            if let Some(response) = self.validate(&mut value, original) {
+                return response;
+            }
+            data.commit(change);
+            response
+        }
+    }
+
+    pub async fn handle_post(
+        &self,
+        request: http::Request<Body>,
+        path: &str,
+    ) -> http::Response<Body> {
+        let data = self.data(&request, true).await;
+        let location = request.uri().path().trim_end_matches('/').to_owned();
+        let mut new_value = match json_request::<serde_json::Value>(request).await {
+            Ok(new_value) => new_value,
+            Err(response) => return response,
+        };
+
+        {
+            let mut value = data.value().write().await;
+            let original = self.needs_original().then(|| value.clone());
+
+            let (mut response, change) = {
+                let (array, change_path) =
+                    match pointer::get_mut_indexed(&mut value, path, &self.id) {
+                        Some((serde_json::Value::Array(array), change_path)) => {
+                            (array, change_path)
+                        }
+                        Some(_) => {
+                            log::info!("Pointer `{}` does not refer to an array", path);
+                            return response::from_status(http::StatusCode::METHOD_NOT_ALLOWED);
+                        }
+                        None => {
+                            log::info!("Pointer `{}` did not match JSON", path);
+                            return response::from_status(http::StatusCode::NOT_FOUND);
+                        }
+                    };
+
+                let token = match pointer::assign_id(array, &mut new_value, &self.id) {
+                    Some(token) => token,
+                    None => {
+                        log::info!("An element of `{}` already has the given id", path);
+                        return response::from_status(http::StatusCode::CONFLICT);
+                    }
+                };
+                let change =
+                    changes::operation("add", &format!("{}/-", change_path), Some(&new_value));
+                array.push(new_value);
+
+                let mut response = etag::json(&array[array.len() - 1]);
+                response::set_header(
+                    &mut response,
+                    http::header::LOCATION,
+                    &format!("{}/{}", location, encode(&pointer::escape(&token))),
+                );
+                (response, change)
+            };
+            *response.status_mut() = http::StatusCode::CREATED;
+
+            if let Some(response) = self.validate(&mut value, original) {
+                return response;
+            }
+            data.commit(change);
+            response
+        }
+    }
+
+    pub async fn handle_delete(
+        &self,
+        request: http::Request<Body>,
+        path: &str,
+    ) -> http::Response<Body> {
+        let data = self.data(&request, true).await;
+        let if_match = request.headers().typed_get::<IfMatch>();
+        if path.is_empty() {
+            log::info!("Cannot delete the root of the JSON document");
+            return response::from_status(http::StatusCode::METHOD_NOT_ALLOWED);
+        }
+
+        {
+            let mut value = data.value().write().await;
+            let subvalue = match pointer::get(&value, path, &self.id) {
+                Some(subvalue) => subvalue,
+                None => {
+                    log::info!("Pointer `{}` did not match JSON", path);
+                    return response::from_status(http::StatusCode::NOT_FOUND);
+                }
+            };
+
+            if !etag::if_match_passes(&if_match, subvalue) {
+                log::info!("Precondition failed for pointer `{}`", path);
+                return response::from_status(http::StatusCode::PRECONDITION_FAILED);
+            }
+
+            let change_path = pointer::index_path(&value, path, &self.id).unwrap_or_default();
+            let original = self.needs_original().then(|| value.clone());
+            let removed = pointer::remove(&mut value, path, &self.id);
+
+            let mut change = changes::operation("remove", &change_path, None);
+            if let (Some(removed), Some((collection, true))) =
+                (removed, relations::collection(path))
+            {
+                let operations = relations::cascade(
+                    &mut value,
+                    &collection,
+                    &removed,
+                    &self.relations,
+                    &self.id,
+                );
+                if let serde_json::Value::Array(change) = &mut change {
+                    change.extend(operations);
+                }
+            }
+
+            if let Some(response) = self.validate(&mut value, original) {
+                return response;
+            }
+            data.commit(change);
+        }
+
+        response::from_status(http::StatusCode::NO_CONTENT)
+    }
+
+    /// Returns the data a request operates on. Requests in a session get the session's copy, which
+    /// is made on the first write.
+    async fn data(&self, request: &http::Request<Body>, write: bool) -> Data<'_> {
+        match self.registry.session(request) {
+            Some(id) => self.state.session_data(id, write).await,
+            None => Data::Shared(&self.state),
+        }
+    }
+
+    /// Whether modifications must be checked by `validate`, which needs a copy of the original
+    /// document.
+    fn needs_original(&self) -> bool {
+        let state = &self.state;
+        state.schema.is_some() || state.is_dir || state.format == config::JsonFormat::Toml
+    }
+
+    /// Checks a modified document with `State::check`. If it is invalid, the original document is
+    /// restored and an error response is returned.
+    fn validate(
+        &self,
+        value: &mut serde_json::Value,
+        original: Option<serde_json::Value>,
+    ) -> Option<http::Response<Body>> {
+        let response = self.state.check(value)?;
+        *value = original.expect("original value is saved when data is checked");
+        Some(response)
+    }
+}
+
+impl State {
+    /// Checks a document against the route's schema, and that it can be written to the route's
+    /// files, returning an error response if it is invalid.
+    fn check(&self, value: &serde_json::Value) -> Option<http::Response<Body>> {
+        if let Err(err) = format::check(self.format, value, self.is_dir) {
+            log::info!("Data can't be written as {}: {}", self.format, err);
+            return Some(response::from_status(
+                http::StatusCode::UNPROCESSABLE_ENTITY,
+            ));
+        }
+
+        let schema = self.schema.as_ref()?;
+        schema
+            .validate(value)
+            .err()
+            .map(schema::violations_response)
+    }
+}
+
+/// Parses a PATCH body as either a JSON Patch (RFC 6902) or a JSON Merge Patch (RFC 7396),
+/// depending on its content type. Requests without a specific patch type are treated as JSON Patch.
+async fn patch_request(request: http::Request<Body>) -> Result<PatchRequest, http::Response<Body>> {
+    let is_merge = match request_mime(&request).map_err(response::from_status)? {
+        Some(mime) if mime.type_() == mime::APPLICATION && mime.subtype() == "merge-patch" => true,
+        Some(mime) if mime.type_() == mime::APPLICATION && mime.subtype() == "json-patch" => false,
+        Some(mime) if mime.type_() == mime::APPLICATION && mime.subtype() == mime::JSON => false,
+        None => false,
+        Some(mime) => {
+            log::info!("Unsupported patch content type `{}`", mime);
+            let mut response = response::from_status(http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            response.headers_mut().insert(
+                http::header::HeaderName::from_static("accept-patch"),
+                http::HeaderValue::from_static(
+                    "application/json-patch+json, application/merge-patch+json",
+                ),
+            );
+            return Err(response);
+        }
+    };
+
+    if is_merge {
+        read_json(request).await.map(PatchRequest::Merge)
+    } else {
+        read_json(request).await.map(PatchRequest::Json)
+    }
+}
+
+async fn json_request<T: DeserializeOwned>(
+    request: http::Request<Body>,
+) -> Result<T, http::Response<Body>> {
+    if let Some(mime) = request_mime(&request).map_err(response::from_status)? {
+        if mime.subtype() != mime::JSON && mime.suffix() != Some(mime::JSON) {
+            return Err(response::from_status(
+                http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ));
+        }
+    }
+
+    read_json(request).await
+}
+
+fn request_mime(request: &http::Request<Body>) -> Result<Option<Mime>, http::StatusCode> {
+    match request.headers().typed_try_get::<ContentType>() {
+        Ok(content_type) => Ok(content_type.map(Mime::from)),
+        Err(err) => {
+            log::info!("Error parsing content-type header: {}", err);
+            Err(http::StatusCode::BAD_REQUEST)
+        }
+    }
+}
+
+pub(super) async fn read_json<T: DeserializeOwned>(
+    request: http::Request<Body>,
+) -> Result<T, http::Response<Body>> {
+    let buf = match body::aggregate(request.into_body()).await {
+        Ok(buf) => buf,
+        Err(err) => {
+            log::error!("Error reading request body: {}", err);
+            return Err(response::from_status(
+                http::StatusCode::INTERNAL_SERVER_ERROR,
+            ));
+        }
+    };
+
+    match serde_json::from_reader(buf.reader()) {
+        Ok(value) => Ok(value),
+        Err(err) => {
+            log::info!("Error deserializing request body: {}", err);
+            Err(response::from_status(http::StatusCode::BAD_REQUEST))
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_patch_request() {
+    fn request(content_type: Option<&str>, body: &'static str) -> http::Request<Body> {
+        let mut request = http::Request::patch("/");
+        if let Some(content_type) = content_type {
+            request = request.header(http::header::CONTENT_TYPE, content_type);
+        }
+        request.body(Body::from(body)).unwrap()
+    }
+
+    let merge = request(Some("application/merge-patch+json"), r#"{"a": 1}"#);
+    assert!(matches!(
+        patch_request(merge).await,
+        Ok(PatchRequest::Merge(_))
+    ));
+    let json_patch = r#"[{"op": "add", "path": "/a", "value": 1}]"#;
+    for content_type in &[
+        Some("application/json-patch+json"),
+        Some("application/json"),
+        None,
+    ] {
+        assert!(matches!(
+            patch_request(request(*content_type, json_patch)).await,
+            Ok(PatchRequest::Json(_))
+        ));
+    }
+
+    let response = patch_request(request(Some("text/plain"), "a=1"))
+        .await
+        .unwrap_err();
+    assert_eq!(response.status(), http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(
+        response.headers()["accept-patch"],
+        "application/json-patch+json, application/merge-patch+json"
+    );
+}
+
+#[tokio::test]
+async fn test_preconditions() {
+    async fn send(
+        handler: &JsonHandler,
+        method: http::Method,
+        header: Option<(http::header::HeaderName, &http::HeaderValue)>,
+        body: &'static str,
+    ) -> http::Response<Body> {
+        let mut request = http::Request::builder()
+            .method(method)
+            .uri("/items/1")
+            .header(http::header::CONTENT_TYPE, "application/json");
+        if let Some((name, value)) = header {
+            request = request.header(name, value);
+        }
+        let request = request.body(Body::from(body)).unwrap();
+        handler.handle(request, "/items/1").await.unwrap()
+    }
+
+    let path = std::env::temp_dir().join(format!("{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, r#"{"items": [{"id": 1, "name": "first"}]}"#).unwrap();
+    let config = serde_yaml::from_str(&format!("{{ path: '{}' }}", path.display())).unwrap();
+    let handler = JsonHandler::new(config, Arc::new(JsonRegistry::new(None)))
+        .await
+        .unwrap();
+    let put = r#"{"id": 1, "name": "second"}"#;
+
+    let response = send(&handler, http::Method::GET, None, "").await;
+    assert_eq!(response.status(), http::StatusCode::OK);
+    let etag = response.headers()[http::header::ETAG].clone();
+
+    let if_none_match = Some((http::header::IF_NONE_MATCH, &etag));
+    let response = send(&handler, http::Method::GET, if_none_match.clone(), "").await;
+    assert_eq!(response.status(), http::StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()[http::header::ETAG], etag);
+
+    let if_match = Some((http::header::IF_MATCH, &etag));
+    let response = send(&handler, http::Method::PUT, if_match.clone(), put).await;
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_ne!(response.headers()[http::header::ETAG], etag);
+
+    // The entity tag is now stale.
+    let response = send(&handler, http::Method::GET, if_none_match, "").await;
+    assert_eq!(response.status(), http::StatusCode::OK);
+    for method in &[http::Method::PUT, http::Method::PATCH, http::Method::DELETE] {
+        let body = if *method == http::Method::PATCH {
+            "[]"
+        } else {
+            put
+        };
+        let response = send(&handler, method.clone(), if_match.clone(), body).await;
+        assert_eq!(response.status(), http::StatusCode::PRECONDITION_FAILED);
+    }
+    let response = send(&handler, http::Method::GET, None, "").await;
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body, r#"{"id":1,"name":"second"}"#);
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,217 @@
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::config;
+
+/// Resolves a JSON pointer, addressing array elements by their id field where the array contains
+/// objects with ids, and by index otherwise.
+pub fn get<'a>(value: &'a Value, path: &str, id: &config::JsonId) -> Option<&'a Value> {
+    tokens(path)?.try_fold(value, |value, token| match value {
+        Value::Object(object) => object.get(&token),
+        Value::Array(array) => array_index(array, &token, id).map(|index| &array[index]),
+        _ => None,
+    })
+}
+
+pub fn get_mut<'a>(value: &'a mut Value, path: &str, id: &config::JsonId) -> Option<&'a mut Value> {
+    tokens(path)?.try_fold(value, |value, token| match value {
+        Value::Object(object) => object.get_mut(&token),
+        Value::Array(array) => {
+            let index = array_index(array, &token, id)?;
+            Some(&mut array[index])
+        }
+        _ => None,
+    })
+}
+
+/// Like `get_mut`, but also returns the pointer converted by `index_path`.
+pub fn get_mut_indexed<'a>(
+    value: &'a mut Value,
+    path: &str,
+    id: &config::JsonId,
+) -> Option<(&'a mut Value, String)> {
+    let index_path = index_path(value, path, id)?;
+    Some((get_mut(value, path, id)?, index_path))
+}
+
+/// Converts a JSON pointer, which may address array elements by their id, into a standard JSON
+/// pointer addressing them by index, for use in published JSON Patches.
+pub fn index_path(value: &Value, path: &str, id: &config::JsonId) -> Option<String> {
+    let mut index_path = String::new();
+    let mut value = value;
+    for token in tokens(path)? {
+        value = match value {
+            Value::Object(object) => {
+                index_path.push('/');
+                index_path.push_str(&escape(&token));
+                object.get(&token)?
+            }
+            Value::Array(array) => {
+                let index = array_index(array, &token, id)?;
+                index_path.push('/');
+                index_path.push_str(&index.to_string());
+                &array[index]
+            }
+            _ => return None,
+        };
+    }
+    Some(index_path)
+}
+
+/// Removes the value addressed by a JSON pointer from its parent, returning it.
+pub fn remove(value: &mut Value, path: &str, id: &config::JsonId) -> Option<Value> {
+    let (parent_path, key) = split(path)?;
+    match get_mut(value, parent_path, id)? {
+        Value::Object(object) => object.remove(&key),
+        Value::Array(array) => {
+            let index = array_index(array, &key, id)?;
+            Some(array.remove(index))
+        }
+        _ => None,
+    }
+}
+
+/// Assigns an id to an object being appended to `array`, returning the (unescaped) reference token
+/// which addresses it once appended. Returns `None` if the object already has an id which is used
+/// by an element of `array`.
+pub fn assign_id(array: &[Value], item: &mut Value, id: &config::JsonId) -> Option<String> {
+    let object = match item {
+        Value::Object(object) => object,
+        _ => return Some(array.len().to_string()),
+    };
+
+    if let Some(existing) = object.get(&id.field).and_then(id_token) {
+        let is_used = array
+            .iter()
+            .any(|element| element.get(&id.field).and_then(id_token).as_ref() == Some(&existing));
+        return if is_used { None } else { Some(existing) };
+    }
+
+    let new_id = match id.strategy {
+        config::JsonIdStrategy::Increment => {
+            let max = array
+                .iter()
+                .filter_map(|element| element.get(&id.field)?.as_u64())
+                .max();
+            Value::from(max.map_or(1, |max| max + 1))
+        }
+        config::JsonIdStrategy::Uuid => Value::from(Uuid::new_v4().to_string()),
+        config::JsonIdStrategy::None => return Some(array.len().to_string()),
+    };
+
+    let token = id_token(&new_id).expect("generated id is a string or number");
+    object.insert(id.field.clone(), new_id);
+    Some(token)
+}
+
+/// Splits a JSON pointer into the pointer to its parent and its final (unescaped) reference token.
+pub fn split(path: &str) -> Option<(&str, String)> {
+    let index = path.rfind('/')?;
+    Some((&path[..index], unescape(&path[index + 1..])))
+}
+
+pub fn tokens(path: &str) -> Option<impl Iterator<Item = String> + '_> {
+    if !path.is_empty() && !path.starts_with('/') {
+        return None;
+    }
+    Some(path.split('/').skip(1).map(unescape))
+}
+
+fn unescape(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+pub fn escape(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/// Returns the reference token which addresses the element of `array` at `index`, or `None` if the
+/// element has no id while others do, in which case it can't be addressed.
+pub fn element_token(array: &[Value], index: usize, id: &config::JsonId) -> Option<String> {
+    if array.iter().any(|element| element.get(&id.field).is_some()) {
+        array[index].get(&id.field).and_then(id_token)
+    } else {
+        Some(index.to_string())
+    }
+}
+
+fn array_index(array: &[Value], token: &str, id: &config::JsonId) -> Option<usize> {
+    if array.iter().any(|element| element.get(&id.field).is_some()) {
+        array
+            .iter()
+            .position(|element| element.get(&id.field).and_then(id_token).as_deref() == Some(token))
+    } else {
+        token.parse().ok().filter(|&index| index < array.len())
+    }
+}
+
+pub fn id_token(id: &Value) -> Option<String> {
+    match id {
+        Value::String(string) => Some(string.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_split() {
+    assert_eq!(split(""), None);
+    assert_eq!(split("/a"), Some(("", "a".to_owned())));
+    assert_eq!(split("/a/0"), Some(("/a", "0".to_owned())));
+    assert_eq!(split("/a/b~1c~0d"), Some(("/a", "b/c~d".to_owned())));
+}
+
+#[test]
+fn test_get_by_id() {
+    let id = config::JsonId::default();
+    let value = serde_json::json!({
+        "posts": [{ "id": 3, "title": "a" }, { "id": 7, "title": "b" }],
+        "tags": ["x", "y"],
+    });
+
+    assert_eq!(get(&value, "/posts/7/title", &id), Some(&Value::from("b")));
+    assert_eq!(get(&value, "/posts/1", &id), None);
+    assert_eq!(get(&value, "/tags/1", &id), Some(&Value::from("y")));
+    assert_eq!(get(&value, "", &id), Some(&value));
+}
+
+#[test]
+fn test_index_path() {
+    let id = config::JsonId::default();
+    let value = serde_json::json!({
+        "posts": [{ "id": 3, "title": "a" }, { "id": 7, "title": "b" }],
+        "a/b": ["x", "y"],
+    });
+
+    assert_eq!(
+        index_path(&value, "/posts/7/title", &id).as_deref(),
+        Some("/posts/1/title")
+    );
+    assert_eq!(
+        index_path(&value, "/a~1b/1", &id).as_deref(),
+        Some("/a~1b/1")
+    );
+    assert_eq!(index_path(&value, "", &id).as_deref(), Some(""));
+    assert_eq!(index_path(&value, "/posts/1", &id), None);
+}
+
+#[test]
+fn test_assign_id() {
+    let id = config::JsonId::default();
+    let array = vec![
+        serde_json::json!({ "id": 3 }),
+        serde_json::json!({ "id": 7 }),
+    ];
+
+    let mut item = serde_json::json!({ "title": "c" });
+    assert_eq!(assign_id(&array, &mut item, &id).as_deref(), Some("8"));
+    assert_eq!(item, serde_json::json!({ "id": 8, "title": "c" }));
+
+    let mut item = serde_json::json!({ "id": "custom" });
+    assert_eq!(assign_id(&array, &mut item, &id).as_deref(), Some("custom"));
+
+    let mut item = serde_json::json!({ "id": 7 });
+    assert_eq!(assign_id(&array, &mut item, &id), None);
+    let mut item = serde_json::json!({ "id": "7" });
+    assert_eq!(assign_id(&array, &mut item, &id), None);
+}
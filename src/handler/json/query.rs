@@ -0,0 +1,391 @@
+use std::cmp::Ordering;
+use std::fmt::Write;
+
+use regex::{Regex, RegexBuilder};
+use serde_json::Value;
+use urlencoding::{decode, encode};
+
+use super::relations::Includes;
+
+/// Filtering, sorting and pagination parameters for a GET request on an array.
+#[derive(Debug, Default)]
+pub struct Query {
+    pairs: Vec<(String, String)>,
+    filters: Vec<Filter>,
+    search: Option<String>,
+    sort: Vec<(String, bool)>,
+    page: Option<usize>,
+    limit: Option<usize>,
+    start: Option<usize>,
+    end: Option<usize>,
+    pub includes: Includes,
+    /// A JSONPath expression, given as `query` (or `_query`), selecting the parts of the addressed
+    /// value to return. The selected values are then filtered, sorted and paginated like an array.
+    pub expression: Option<String>,
+}
+
+#[derive(Debug)]
+struct Filter {
+    field: String,
+    op: FilterOp,
+}
+
+#[derive(Debug)]
+enum FilterOp {
+    Eq(Vec<String>),
+    Ne(String),
+    Gte(String),
+    Lte(String),
+    Like(Regex),
+}
+
+/// The result of applying a query to an array.
+#[derive(Debug)]
+pub struct Listing<'a> {
+    pub items: Vec<&'a Value>,
+    pub total: usize,
+    pub link: Option<String>,
+}
+
+const DEFAULT_PAGE_LIMIT: usize = 10;
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let mut result = Query::default();
+        let mut sort = Vec::new();
+        let mut order = Vec::new();
+
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let mut parts = pair.splitn(2, '=');
+            let key = decode_component(parts.next().unwrap_or_default())?;
+            let value = decode_component(parts.next().unwrap_or_default())?;
+
+            match key.as_str() {
+                "q" => result.search = Some(value.to_lowercase()),
+                "query" | "_query" => result.expression = Some(value.clone()),
+                "_sort" => sort.extend(value.split(',').map(str::to_owned)),
+                "_order" => order.extend(value.split(',').map(|order| order == "desc")),
+                "_page" => result.page = Some(parse_number(&key, &value)?.max(1)),
+                "_limit" => result.limit = Some(parse_number(&key, &value)?),
+                "_start" => result.start = Some(parse_number(&key, &value)?),
+                "_end" => result.end = Some(parse_number(&key, &value)?),
+                "_expand" => result
+                    .includes
+                    .expand
+                    .extend(value.split(',').map(str::to_owned)),
+                "_embed" => result
+                    .includes
+                    .embed
+                    .extend(value.split(',').map(str::to_owned)),
+                _ if key.starts_with('_') => (),
+                _ => result.add_filter(&key, value.clone())?,
+            }
+
+            result.pairs.push((key, value));
+        }
+
+        result.sort = sort
+            .into_iter()
+            .enumerate()
+            .map(|(index, field)| (field, order.get(index).copied().unwrap_or(false)))
+            .collect();
+        Ok(result)
+    }
+
+    fn add_filter(&mut self, key: &str, value: String) -> Result<(), String> {
+        let (field, op) = if let Some(field) = strip_suffix(key, "_ne") {
+            (field, FilterOp::Ne(value))
+        } else if let Some(field) = strip_suffix(key, "_gte") {
+            (field, FilterOp::Gte(value))
+        } else if let Some(field) = strip_suffix(key, "_lte") {
+            (field, FilterOp::Lte(value))
+        } else if let Some(field) = strip_suffix(key, "_like") {
+            let regex = RegexBuilder::new(&value)
+                .case_insensitive(true)
+                .build()
+                .map_err(|err| format!("invalid pattern for `{}`: {}", key, err))?;
+            (field, FilterOp::Like(regex))
+        } else {
+            let existing = self
+                .filters
+                .iter_mut()
+                .find_map(|filter| match &mut filter.op {
+                    FilterOp::Eq(values) if filter.field == key => Some(values),
+                    _ => None,
+                });
+            if let Some(values) = existing {
+                values.push(value);
+                return Ok(());
+            }
+            (key, FilterOp::Eq(vec![value]))
+        };
+
+        self.filters.push(Filter {
+            field: field.to_owned(),
+            op,
+        });
+        Ok(())
+    }
+
+    /// Applies the query to `array`. `path` is the request path, used to build pagination links.
+    pub fn apply<'a>(&self, array: &'a [Value], path: &str) -> Listing<'a> {
+        let mut items: Vec<&Value> = array
+            .iter()
+            .filter(|item| self.filters.iter().all(|filter| filter.is_match(item)))
+            .filter(|item| match &self.search {
+                Some(search) => contains_text(item, search),
+                None => true,
+            })
+            .collect();
+
+        if !self.sort.is_empty() {
+            items.sort_by(|a, b| {
+                self.sort
+                    .iter()
+                    .map(|(field, desc)| {
+                        let ordering = compare(lookup(a, field), lookup(b, field));
+                        if *desc {
+                            ordering.reverse()
+                        } else {
+                            ordering
+                        }
+                    })
+                    .find(|ordering| *ordering != Ordering::Equal)
+                    .unwrap_or(Ordering::Equal)
+            });
+        }
+
+        let total = items.len();
+        let mut link = None;
+
+        if let Some(page) = self.page {
+            let limit = self.limit.unwrap_or(DEFAULT_PAGE_LIMIT).max(1);
+            let last = total.div_ceil(limit).max(1);
+            items = items
+                .into_iter()
+                .skip((page - 1).saturating_mul(limit))
+                .take(limit)
+                .collect();
+            link = Some(self.link_header(path, page, last));
+        } else if self.start.is_some() || self.end.is_some() || self.limit.is_some() {
+            let start = self.start.unwrap_or(0);
+            let end = match (self.end, self.limit) {
+                (Some(end), _) => end,
+                (None, Some(limit)) => start.saturating_add(limit),
+                (None, None) => total,
+            };
+            items = items
+                .into_iter()
+                .skip(start)
+                .take(end.saturating_sub(start))
+                .collect();
+        }
+
+        Listing { items, total, link }
+    }
+
+    fn link_header(&self, path: &str, page: usize, last: usize) -> String {
+        let mut links = vec![("first", 1), ("last", last)];
+        if page > 1 {
+            links.push(("prev", page - 1));
+        }
+        if page < last {
+            links.push(("next", page + 1));
+        }
+
+        let mut header = String::new();
+        for (rel, page) in links {
+            if !header.is_empty() {
+                header.push_str(", ");
+            }
+            write!(header, "<{}?", path).unwrap();
+            let mut first = true;
+            for (key, value) in &self.pairs {
+                if key == "_page" {
+                    continue;
+                }
+                if !first {
+                    header.push('&');
+                }
+                first = false;
+                write!(header, "{}={}", encode(key), encode(value)).unwrap();
+            }
+            if !first {
+                header.push('&');
+            }
+            write!(header, "_page={}>; rel=\"{}\"", page, rel).unwrap();
+        }
+        header
+    }
+}
+
+impl Filter {
+    fn is_match(&self, item: &Value) -> bool {
+        let value = lookup(item, &self.field);
+        match &self.op {
+            FilterOp::Eq(values) => values.iter().any(|expected| equals(value, expected)),
+            FilterOp::Ne(expected) => !equals(value, expected),
+            FilterOp::Gte(bound) => matches!(
+                value.map(|value| compare_to_str(value, bound)),
+                Some(Ordering::Greater) | Some(Ordering::Equal)
+            ),
+            FilterOp::Lte(bound) => matches!(
+                value.map(|value| compare_to_str(value, bound)),
+                Some(Ordering::Less) | Some(Ordering::Equal)
+            ),
+            FilterOp::Like(regex) => match value {
+                Some(Value::String(string)) => regex.is_match(string),
+                Some(value @ Value::Number(_)) | Some(value @ Value::Bool(_)) => {
+                    regex.is_match(&value.to_string())
+                }
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Looks up a field by a dot-separated path, e.g. `author.name`.
+fn lookup<'a>(item: &'a Value, field: &str) -> Option<&'a Value> {
+    field.split('.').try_fold(item, |value, key| match value {
+        Value::Object(object) => object.get(key),
+        Value::Array(array) => array.get(key.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+fn equals(value: Option<&Value>, expected: &str) -> bool {
+    match value {
+        Some(Value::String(string)) => string == expected,
+        Some(Value::Number(number)) => number.as_f64() == expected.parse().ok(),
+        Some(Value::Bool(boolean)) => expected.parse() == Ok(*boolean),
+        Some(Value::Null) => expected == "null",
+        Some(Value::Array(array)) => array.iter().any(|element| equals(Some(element), expected)),
+        _ => false,
+    }
+}
+
+fn compare_to_str(value: &Value, bound: &str) -> Ordering {
+    match (value, bound.parse::<f64>()) {
+        (Value::Number(number), Ok(bound)) => number
+            .as_f64()
+            .and_then(|number| number.partial_cmp(&bound))
+            .unwrap_or(Ordering::Equal),
+        (Value::String(string), _) => string.as_str().cmp(bound),
+        (value, _) => value.to_string().as_str().cmp(bound),
+    }
+}
+
+fn compare(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    fn rank(value: Option<&Value>) -> u8 {
+        match value {
+            None | Some(Value::Null) => 0,
+            Some(Value::Bool(_)) => 1,
+            Some(Value::Number(_)) => 2,
+            Some(Value::String(_)) => 3,
+            Some(Value::Array(_)) => 4,
+            Some(Value::Object(_)) => 5,
+        }
+    }
+
+    match (a, b) {
+        (Some(Value::Bool(a)), Some(Value::Bool(b))) => a.cmp(b),
+        (Some(Value::Number(a)), Some(Value::Number(b))) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (Some(Value::String(a)), Some(Value::String(b))) => a.cmp(b),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+fn contains_text(value: &Value, search: &str) -> bool {
+    match value {
+        Value::String(string) => string.to_lowercase().contains(search),
+        Value::Number(number) => number.to_string().contains(search),
+        Value::Array(array) => array.iter().any(|value| contains_text(value, search)),
+        Value::Object(object) => object.values().any(|value| contains_text(value, search)),
+        Value::Bool(_) | Value::Null => false,
+    }
+}
+
+fn decode_component(component: &str) -> Result<String, String> {
+    decode(&component.replace('+', " ")).map_err(|err| format!("invalid query string: {}", err))
+}
+
+fn parse_number(key: &str, value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{}` must be a non-negative integer", key))
+}
+
+fn strip_suffix<'a>(key: &'a str, suffix: &str) -> Option<&'a str> {
+    if key.len() > suffix.len() && key.ends_with(suffix) {
+        Some(&key[..key.len() - suffix.len()])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+fn query_ids(query: &str, array: &[Value]) -> Vec<u64> {
+    let query = Query::parse(query).unwrap();
+    let listing = query.apply(array, "/items");
+    listing
+        .items
+        .iter()
+        .map(|item| item["id"].as_u64().unwrap())
+        .collect()
+}
+
+#[test]
+fn test_filter_and_sort() {
+    let array = serde_json::json!([
+        { "id": 1, "status": "open", "age": 25, "name": "Alice" },
+        { "id": 2, "status": "closed", "age": 40, "name": "Bob" },
+        { "id": 3, "status": "open", "age": 35, "name": "Carol" },
+    ]);
+    let array = array.as_array().unwrap();
+
+    assert_eq!(query_ids("status=open", array), vec![1, 3]);
+    assert_eq!(query_ids("age_gte=30", array), vec![2, 3]);
+    assert_eq!(query_ids("status=open&age_lte=30", array), vec![1]);
+    assert_eq!(query_ids("id=1&id=2", array), vec![1, 2]);
+    assert_eq!(query_ids("name_like=^b", array), vec![2]);
+    assert_eq!(query_ids("q=car", array), vec![3]);
+    assert_eq!(query_ids("_sort=age&_order=desc", array), vec![2, 3, 1]);
+    assert_eq!(query_ids("_sort=status,age", array), vec![2, 1, 3]);
+}
+
+#[test]
+fn test_pagination() {
+    let array: Vec<Value> = (1..=25).map(|id| serde_json::json!({ "id": id })).collect();
+
+    assert_eq!(query_ids("_page=3", &array), vec![21, 22, 23, 24, 25]);
+    assert_eq!(query_ids("_page=2&_limit=2", &array), vec![3, 4]);
+    assert_eq!(query_ids("_start=5&_end=8", &array), vec![6, 7, 8]);
+    assert_eq!(query_ids("_start=20&_limit=2", &array), vec![21, 22]);
+    assert!(query_ids("_page=18446744073709551615&_limit=2", &array).is_empty());
+    assert_eq!(
+        query_ids("_start=24&_limit=18446744073709551615", &array),
+        vec![25]
+    );
+
+    let query = Query::parse("_page=2&_limit=10").unwrap();
+    let listing = query.apply(&array, "/items");
+    assert_eq!(listing.total, 25);
+    assert_eq!(
+        listing.link.unwrap(),
+        "</items?_limit=10&_page=1>; rel=\"first\", </items?_limit=10&_page=3>; rel=\"last\", \
+         </items?_limit=10&_page=1>; rel=\"prev\", </items?_limit=10&_page=3>; rel=\"next\""
+    );
+}
+
+#[test]
+fn test_expression() {
+    let query = Query::parse("query=%24..title&status=open").unwrap();
+    assert_eq!(query.expression.as_deref(), Some("$..title"));
+    assert_eq!(query.filters.len(), 1);
+
+    let query = Query::parse("_query=%24.id").unwrap();
+    assert_eq!(query.expression.as_deref(), Some("$.id"));
+}
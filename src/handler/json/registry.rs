@@ -0,0 +1,259 @@
+use std::sync::{Arc, Mutex};
+
+use hyper::Body;
+use serde_json::Value;
+use tokio::time;
+
+use super::session::Sessions;
+use super::{changes, State};
+use crate::config;
+
+/// Tracks the state of every json route so that it can be managed through admin routes and
+/// isolated per session.
+#[derive(Debug)]
+pub struct JsonRegistry {
+    states: Mutex<Vec<Arc<State>>>,
+    sessions: Option<Sessions>,
+}
+
+impl JsonRegistry {
+    pub fn new(sessions: Option<config::SessionConfig>) -> Self {
+        JsonRegistry {
+            states: Mutex::new(Vec::new()),
+            sessions: sessions.map(Sessions::new),
+        }
+    }
+
+    pub(super) fn register(&self, state: Arc<State>) {
+        self.states.lock().unwrap().push(state);
+    }
+
+    /// Discards the data of expired sessions periodically. A session may therefore outlive its
+    /// idle timeout by up to one period. The task stops once the registry is dropped.
+    pub fn spawn_session_purge(self: &Arc<Self>) {
+        let period = match &self.sessions {
+            Some(sessions) => sessions.idle_timeout(),
+            None => return,
+        };
+
+        let registry = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = time::interval(period);
+            loop {
+                interval.tick().await;
+                match registry.upgrade() {
+                    Some(registry) => registry.purge_sessions(),
+                    None => break,
+                }
+            }
+        });
+    }
+
+    /// Returns the session a request belongs to, if sessions are enabled.
+    pub(super) fn session(&self, request: &http::Request<Body>) -> Option<String> {
+        let sessions = self.sessions.as_ref()?;
+        let id = sessions.id(request)?;
+
+        sessions.touch(&id);
+        Some(id)
+    }
+
+    fn purge_sessions(&self) {
+        let expired = match &self.sessions {
+            Some(sessions) => sessions.expire(),
+            None => return,
+        };
+        if !expired.is_empty() {
+            log::debug!("Sessions expired: {:?}", expired);
+            for state in self.states.lock().unwrap().iter() {
+                let mut copies = state.sessions.lock().unwrap();
+                for id in &expired {
+                    copies.remove(id);
+                }
+            }
+        }
+    }
+
+    /// Returns the states of all json routes, or just the route with the given name. Returns
+    /// `None` if no route has that name.
+    fn select(&self, name: Option<&str>) -> Option<Vec<Arc<State>>> {
+        let states = self.states.lock().unwrap();
+        match name {
+            Some(name) => states
+                .iter()
+                .find(|state| state.name.as_deref() == Some(name))
+                .map(|state| vec![state.clone()]),
+            None => Some(states.clone()),
+        }
+    }
+
+    /// Lists the names and saved snapshots of all json routes.
+    pub fn list(&self) -> Value {
+        let states = self.states.lock().unwrap();
+        states
+            .iter()
+            .map(|state| {
+                let snapshots: Vec<String> =
+                    state.snapshots.lock().unwrap().keys().cloned().collect();
+                serde_json::json!({ "name": state.name, "snapshots": snapshots })
+            })
+            .collect()
+    }
+
+    /// Restores json routes to the values they were loaded with at startup. Like `restore` and
+    /// `seed`, this discards the sessions' copies of the routes' data, so that every session sees
+    /// the new value.
+    pub async fn reset(&self, name: Option<&str>) -> bool {
+        let states = match self.select(name) {
+            Some(states) => states,
+            None => return false,
+        };
+
+        for state in states {
+            state.replace(state.initial.clone()).await;
+        }
+        true
+    }
+
+    /// Saves the current values of json routes under the given snapshot name.
+    pub async fn snapshot(&self, name: Option<&str>, snapshot: &str) -> bool {
+        let states = match self.select(name) {
+            Some(states) => states,
+            None => return false,
+        };
+
+        for state in states {
+            let value = state.value.read().await.clone();
+            state
+                .snapshots
+                .lock()
+                .unwrap()
+                .insert(snapshot.to_owned(), value);
+        }
+        true
+    }
+
+    /// Restores json routes to a saved snapshot. If any of the routes does not have the snapshot,
+    /// none of them are modified.
+    pub async fn restore(&self, name: Option<&str>, snapshot: &str) -> bool {
+        let states = match self.select(name) {
+            Some(states) => states,
+            None => return false,
+        };
+
+        let mut values = Vec::with_capacity(states.len());
+        for state in &states {
+            match state.snapshots.lock().unwrap().get(snapshot) {
+                Some(value) => values.push(value.clone()),
+                None => return false,
+            }
+        }
+
+        for (state, value) in states.iter().zip(values) {
+            state.replace(value).await;
+        }
+        true
+    }
+
+    /// Replaces the value of the named json route. The value is checked like the data written by
+    /// requests to the route, and an error response is returned if it is invalid.
+    pub async fn seed(&self, name: &str, value: Value) -> Result<bool, http::Response<Body>> {
+        let states = match self.select(Some(name)) {
+            Some(states) => states,
+            None => return Ok(false),
+        };
+
+        for state in &states {
+            if let Some(response) = state.check(&value) {
+                return Err(response);
+            }
+        }
+        for state in states {
+            state.replace(value.clone()).await;
+        }
+        Ok(true)
+    }
+}
+
+impl State {
+    async fn replace(&self, value: Value) {
+        let mut current = self.value.write().await;
+        *current = value;
+        self.sessions.lock().unwrap().clear();
+        self.publish(changes::operation("replace", "", Some(&current)));
+        self.dirty.notify();
+    }
+}
+
+#[tokio::test]
+async fn test_sessions() {
+    use super::JsonHandler;
+
+    async fn get(handler: &JsonHandler, session: Option<&str>) -> Value {
+        let mut request = http::Request::get("/items/1");
+        if let Some(session) = session {
+            request = request.header("x-mock-session", session);
+        }
+        let response = handler
+            .handle(request.body(Body::empty()).unwrap(), "/items/1")
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice::<Value>(&body).unwrap()["name"].clone()
+    }
+
+    async fn put(handler: &JsonHandler, session: Option<&str>, name: &str) {
+        let mut request =
+            http::Request::put("/items/1").header(http::header::CONTENT_TYPE, "application/json");
+        if let Some(session) = session {
+            request = request.header("x-mock-session", session);
+        }
+        let body = serde_json::json!({ "id": 1, "name": name }).to_string();
+        let response = handler
+            .handle(request.body(Body::from(body)).unwrap(), "/items/1")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+    }
+
+    let path = std::env::temp_dir().join(format!("{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, r#"{"items": [{"id": 1, "name": "first"}]}"#).unwrap();
+    let config =
+        serde_yaml::from_str(&format!("{{ name: db, path: '{}' }}", path.display())).unwrap();
+    let registry = Arc::new(JsonRegistry::new(Some(
+        serde_yaml::from_str("{ idle-timeout-secs: 1 }").unwrap(),
+    )));
+    let handler = JsonHandler::new(config, registry.clone()).await.unwrap();
+
+    // A session gets its own copy on its first write, and until then sees the shared data.
+    put(&handler, Some("a"), "a").await;
+    put(&handler, None, "shared").await;
+    assert_eq!(get(&handler, Some("a")).await, "a");
+    assert_eq!(get(&handler, Some("b")).await, "shared");
+    assert_eq!(get(&handler, None).await, "shared");
+
+    // Copies are discarded when the shared data is replaced.
+    assert!(registry.snapshot(None, "saved").await);
+    assert!(registry.reset(None).await);
+    assert_eq!(get(&handler, Some("a")).await, "first");
+
+    put(&handler, Some("a"), "a").await;
+    assert!(registry.restore(None, "saved").await);
+    assert_eq!(get(&handler, Some("a")).await, "shared");
+
+    put(&handler, Some("a"), "a").await;
+    let seeded = serde_json::json!({ "items": [{ "id": 1, "name": "seeded" }] });
+    assert!(registry.seed("db", seeded).await.unwrap());
+    assert_eq!(get(&handler, Some("a")).await, "seeded");
+
+    // Copies are also discarded once their session has been idle for too long.
+    put(&handler, Some("a"), "a").await;
+    registry.purge_sessions();
+    assert_eq!(get(&handler, Some("a")).await, "a");
+    time::delay_for(std::time::Duration::from_secs(1)).await;
+    registry.purge_sessions();
+    assert!(handler.state.sessions.lock().unwrap().is_empty());
+    assert_eq!(get(&handler, Some("a")).await, "seeded");
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,257 @@
+use serde_json::Value;
+
+use super::pointer;
+use crate::config;
+
+/// The relations to include in the items returned by a GET request, from the `_expand` and
+/// `_embed` query parameters.
+#[derive(Debug, Default)]
+pub struct Includes {
+    pub expand: Vec<String>,
+    pub embed: Vec<String>,
+}
+
+impl Includes {
+    pub fn is_empty(&self) -> bool {
+        self.expand.is_empty() && self.embed.is_empty()
+    }
+
+    /// Returns a copy of `item`, an element of the top-level `collection`, with related items
+    /// from `root` included.
+    pub fn apply(
+        &self,
+        root: &Value,
+        collection: &str,
+        item: &Value,
+        relations: &[config::JsonRelation],
+    ) -> Result<Value, String> {
+        let mut item = item.clone();
+        let object = match &mut item {
+            Value::Object(object) => object,
+            _ => return Ok(item),
+        };
+
+        for name in &self.expand {
+            let relation = relations
+                .iter()
+                .find(|relation| {
+                    relation.from.collection == collection && relation.expand_name() == name
+                })
+                .ok_or_else(|| format!("unknown relation `{}` for `_expand`", name))?;
+
+            let key = object.get(&relation.from.field);
+            let parent = elements(root, &relation.to.collection)
+                .find(|parent| same_key(parent.get(&relation.to.field), key));
+            if let Some(parent) = parent {
+                object.insert(name.clone(), parent.clone());
+            }
+        }
+
+        for name in &self.embed {
+            let relation = relations
+                .iter()
+                .find(|relation| {
+                    relation.to.collection == collection && relation.embed_name() == name
+                })
+                .ok_or_else(|| format!("unknown relation `{}` for `_embed`", name))?;
+
+            let key = object.get(&relation.to.field);
+            let children = elements(root, &relation.from.collection)
+                .filter(|child| same_key(child.get(&relation.from.field), key))
+                .cloned()
+                .collect();
+            object.insert(name.clone(), Value::Array(children));
+        }
+
+        Ok(item)
+    }
+}
+
+/// Returns the name of the top-level collection a JSON pointer refers to, and whether it refers to
+/// a single item in it rather than the whole collection.
+pub fn collection(path: &str) -> Option<(String, bool)> {
+    let mut tokens = pointer::tokens(path)?;
+    let collection = tokens.next()?;
+    match (tokens.next(), tokens.next()) {
+        (None, _) => Some((collection, false)),
+        (Some(_), None) => Some((collection, true)),
+        (Some(_), Some(_)) => None,
+    }
+}
+
+/// Removes the items that refer to a deleted item through relations with `cascade` set,
+/// recursively. Returns the JSON Patch operations describing the removals, in order.
+pub fn cascade(
+    root: &mut Value,
+    collection: &str,
+    deleted: &Value,
+    relations: &[config::JsonRelation],
+    id: &config::JsonId,
+) -> Vec<Value> {
+    let mut operations = Vec::new();
+
+    for relation in relations {
+        if !relation.cascade || relation.to.collection != collection {
+            continue;
+        }
+        let key = match deleted.get(&relation.to.field) {
+            Some(key) => key,
+            None => continue,
+        };
+
+        while let Some(token) = referring_item(root, relation, key, id) {
+            let path = format!(
+                "/{}/{}",
+                pointer::escape(&relation.from.collection),
+                pointer::escape(&token)
+            );
+            let index_path = match pointer::index_path(root, &path, id) {
+                Some(index_path) => index_path,
+                None => break,
+            };
+            let child = match pointer::remove(root, &path, id) {
+                Some(child) => child,
+                None => break,
+            };
+            operations.push(serde_json::json!({ "op": "remove", "path": index_path }));
+            operations.extend(cascade(
+                root,
+                &relation.from.collection,
+                &child,
+                relations,
+                id,
+            ));
+        }
+    }
+
+    operations
+}
+
+/// Returns the reference token of the first item referring to `key` through `relation`. Items
+/// without an id are skipped if others in their collection have one, since they can't be addressed.
+fn referring_item(
+    root: &Value,
+    relation: &config::JsonRelation,
+    key: &Value,
+    id: &config::JsonId,
+) -> Option<String> {
+    let array = root.get(&relation.from.collection)?.as_array()?;
+    array
+        .iter()
+        .enumerate()
+        .filter(|(_, child)| same_key(child.get(&relation.from.field), Some(key)))
+        .find_map(|(index, _)| pointer::element_token(array, index, id))
+}
+
+fn elements<'a>(root: &'a Value, collection: &str) -> impl Iterator<Item = &'a Value> {
+    root.get(collection)
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+}
+
+/// Compares keys by their string form, so that `"1"` matches `1`.
+fn same_key(a: Option<&Value>, b: Option<&Value>) -> bool {
+    match (a.and_then(pointer::id_token), b.and_then(pointer::id_token)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+fn relation(from: &str, to: &str, cascade: bool) -> config::JsonRelation {
+    serde_json::from_value(serde_json::json!({ "from": from, "to": to, "cascade": cascade }))
+        .unwrap()
+}
+
+#[test]
+fn test_includes() {
+    let relations = vec![relation("comments.postId", "posts.id", false)];
+    let root = serde_json::json!({
+        "posts": [{ "id": 1, "title": "a" }, { "id": 2, "title": "b" }],
+        "comments": [{ "id": 1, "postId": 1 }, { "id": 2, "postId": "1" }],
+    });
+
+    let expand = Includes {
+        expand: vec!["post".to_owned()],
+        embed: vec![],
+    };
+    assert_eq!(
+        expand
+            .apply(&root, "comments", &root["comments"][0], &relations)
+            .unwrap(),
+        serde_json::json!({ "id": 1, "postId": 1, "post": { "id": 1, "title": "a" } })
+    );
+
+    let embed = Includes {
+        expand: vec![],
+        embed: vec!["comments".to_owned()],
+    };
+    assert_eq!(
+        embed
+            .apply(&root, "posts", &root["posts"][0], &relations)
+            .unwrap()["comments"],
+        root["comments"]
+    );
+    assert!(embed
+        .apply(&root, "comments", &root["comments"][0], &relations)
+        .is_err());
+}
+
+#[test]
+fn test_cascade() {
+    let relations = vec![
+        relation("comments.postId", "posts.id", true),
+        relation("replies.commentId", "comments.id", true),
+    ];
+    let id = config::JsonId::default();
+    let mut root = serde_json::json!({
+        "posts": [{ "id": 2 }],
+        "comments": [{ "id": 1, "postId": 1 }, { "id": 2, "postId": 2 }],
+        "replies": [{ "id": 1, "commentId": 1 }, { "id": 2, "commentId": 2 }],
+    });
+
+    let operations = cascade(
+        &mut root,
+        "posts",
+        &serde_json::json!({ "id": 1 }),
+        &relations,
+        &id,
+    );
+    assert_eq!(
+        operations,
+        vec![
+            serde_json::json!({ "op": "remove", "path": "/comments/0" }),
+            serde_json::json!({ "op": "remove", "path": "/replies/0" }),
+        ]
+    );
+    assert_eq!(
+        root["comments"],
+        serde_json::json!([{ "id": 2, "postId": 2 }])
+    );
+    assert_eq!(
+        root["replies"],
+        serde_json::json!([{ "id": 2, "commentId": 2 }])
+    );
+
+    // Items without an id can't be told apart by index from items with an id, so they're kept.
+    let mut root = serde_json::json!({
+        "posts": [],
+        "comments": [{ "postId": 1 }, { "id": 0, "postId": 2 }, { "id": 5, "postId": 1 }],
+    });
+    let operations = cascade(
+        &mut root,
+        "posts",
+        &serde_json::json!({ "id": 1 }),
+        &relations,
+        &id,
+    );
+    assert_eq!(
+        operations,
+        vec![serde_json::json!({ "op": "remove", "path": "/comments/2" })]
+    );
+    assert_eq!(
+        root["comments"],
+        serde_json::json!([{ "postId": 1 }, { "id": 0, "postId": 2 }])
+    );
+}
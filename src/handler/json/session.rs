@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use headers::{Cookie, HeaderMapExt};
+use hyper::Body;
+use serde_json::Value;
+use tokio::sync::RwLock;
+use tokio::time::Instant;
+
+use super::State;
+use crate::config;
+
+/// Tracks when each session was last used, so that their data can be discarded once they expire.
+#[derive(Debug)]
+pub struct Sessions {
+    config: config::SessionConfig,
+    last_used: Mutex<HashMap<String, Instant>>,
+}
+
+/// The data a request operates on: either the shared data of a json route, or a session's copy.
+pub enum Data<'a> {
+    Shared(&'a State),
+    Session(Arc<RwLock<Value>>),
+}
+
+impl Sessions {
+    pub fn new(config: config::SessionConfig) -> Self {
+        Sessions {
+            config,
+            last_used: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the id of the session a request belongs to, from its header or else its cookie.
+    pub fn id(&self, request: &http::Request<Body>) -> Option<String> {
+        let header = request
+            .headers()
+            .get(self.config.header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty());
+        if let Some(header) = header {
+            return Some(header.to_owned());
+        }
+
+        let name = self.config.cookie.as_ref()?;
+        let cookie = request.headers().typed_get::<Cookie>()?;
+        cookie
+            .get(name)
+            .filter(|value| !value.is_empty())
+            .map(str::to_owned)
+    }
+
+    /// How long a session can go unused before it expires.
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.idle_timeout_secs)
+    }
+
+    /// Records that a session was used.
+    pub fn touch(&self, id: &str) {
+        self.last_used
+            .lock()
+            .unwrap()
+            .insert(id.to_owned(), Instant::now());
+    }
+
+    /// Forgets the sessions that have expired, returning their ids.
+    pub fn expire(&self) -> Vec<String> {
+        let now = Instant::now();
+        let timeout = self.idle_timeout();
+        let mut last_used = self.last_used.lock().unwrap();
+
+        let expired: Vec<String> = last_used
+            .iter()
+            .filter(|(_, used)| now.duration_since(**used) >= timeout)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            last_used.remove(id);
+        }
+        expired
+    }
+}
+
+impl State {
+    /// Returns the data for a session. A copy of the shared data is only made once the session
+    /// modifies it, so until then the session sees changes made to the shared data.
+    pub(super) async fn session_data(&self, id: String, write: bool) -> Data<'_> {
+        if let Some(value) = self.sessions.lock().unwrap().get(&id) {
+            return Data::Session(value.clone());
+        }
+        if !write {
+            return Data::Shared(self);
+        }
+
+        let copy = self.value.read().await.clone();
+        let value = self
+            .sessions
+            .lock()
+            .unwrap()
+            .entry(id)
+            .or_insert_with(|| Arc::new(RwLock::new(copy)))
+            .clone();
+        Data::Session(value)
+    }
+}
+
+impl Data<'_> {
+    pub fn value(&self) -> &RwLock<Value> {
+        match self {
+            Data::Shared(state) => &state.value,
+            Data::Session(value) => value,
+        }
+    }
+
+    /// Notifies subscribers of a change and schedules a write to disk. Changes to a session's
+    /// copy are neither published nor persisted.
+    pub fn commit(&self, change: Value) {
+        if let Data::Shared(state) = self {
+            state.publish(change);
+            state.dirty.notify();
+        }
+    }
+}
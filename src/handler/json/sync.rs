@@ -0,0 +1,375 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::bail;
+use serde_json::{Map, Value};
+use tokio::fs;
+use tokio::io::{self, AsyncWriteExt};
+use tokio::time::{self, Instant};
+
+use super::files::{self, Versions};
+use super::{changes, format, State};
+use crate::config;
+
+/// Writes changes to a json route's data back to its file, and optionally reloads the data when
+/// the file is changed by another process.
+///
+/// If the route is backed by a directory, each top-level key is written to its own file, and only
+/// keys whose values have changed are written.
+#[derive(Debug)]
+pub struct Sync {
+    state: Arc<State>,
+    config: config::JsonRoute,
+    buf: Vec<u8>,
+    versions: Option<Versions>,
+    /// The top-level keys last read from or written to a directory.
+    synced: Map<String, Value>,
+}
+
+impl Sync {
+    pub async fn new(state: Arc<State>, config: config::JsonRoute) -> Self {
+        let versions = files::versions(&config).await.ok();
+        let synced = match &state.initial {
+            Value::Object(object) if config.is_dir() => object.clone(),
+            _ => Map::new(),
+        };
+        Sync {
+            state,
+            config,
+            buf: Vec::new(),
+            versions,
+            synced,
+        }
+    }
+
+    pub async fn run(mut self) {
+        let debounce = Duration::from_millis(self.config.debounce_ms);
+        let mut watch = time::interval(Duration::from_millis(self.config.watch_interval_ms.max(1)));
+        let mut deadline: Option<Instant> = None;
+
+        loop {
+            let write_at = deadline.unwrap_or_else(Instant::now);
+            tokio::select! {
+                _ = self.state.dirty.notified() => {
+                    // Writes are delayed by up to `debounce-ms` so that bursts of changes are
+                    // coalesced into a single write.
+                    deadline.get_or_insert_with(|| Instant::now() + debounce);
+                }
+                _ = time::delay_until(write_at), if deadline.is_some() => {
+                    deadline = None;
+                    self.write().await;
+                }
+                _ = watch.tick(), if self.config.watch => {
+                    if !self.check_for_changes(deadline.is_some()).await {
+                        deadline = None;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn write(&mut self) {
+        log::trace!(
+            "Syncing {} data to file `{}`",
+            self.config.format(),
+            self.config.path.display()
+        );
+
+        let result = self.write_file().await;
+        self.buf.clear();
+
+        if let Err(err) = result {
+            log::error!(
+                "Failed to write {} data to file `{}`: {}",
+                self.config.format(),
+                self.config.path.display(),
+                err
+            );
+        }
+
+        self.versions = files::versions(&self.config).await.ok();
+    }
+
+    async fn write_file(&mut self) -> anyhow::Result<()> {
+        if self.config.is_dir() {
+            return self.write_dir().await;
+        }
+
+        self.fill_buf().await?;
+        write_atomic(&self.config.path, &self.buf).await?;
+        Ok(())
+    }
+
+    async fn write_dir(&mut self) -> anyhow::Result<()> {
+        let (changed, removed) = {
+            let value = self.state.value.read().await;
+            let object = match &*value {
+                Value::Object(object) => object,
+                _ => bail!("data stored in a directory must be an object"),
+            };
+
+            let changed: Vec<(String, Value)> = object
+                .iter()
+                .filter(|(key, value)| self.synced.get(*key) != Some(value))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            let removed: Vec<String> = self
+                .synced
+                .keys()
+                .filter(|key| !object.contains_key(*key))
+                .cloned()
+                .collect();
+            (changed, removed)
+        };
+
+        for (key, value) in changed {
+            let path = match files::key_path(&self.config, &key) {
+                Some(path) => path,
+                None => {
+                    log::warn!("Not writing key `{}`, which is not a valid file name", key);
+                    continue;
+                }
+            };
+            self.buf.clear();
+            format::write(
+                self.config.format(),
+                &value,
+                self.config.pretty,
+                &mut self.buf,
+            )?;
+            write_atomic(&path, &self.buf).await?;
+            self.synced.insert(key, value);
+        }
+
+        for key in removed {
+            if let Some(path) = files::key_path(&self.config, &key) {
+                fs::remove_file(&path).await?;
+            }
+            self.synced.remove(&key);
+        }
+        Ok(())
+    }
+
+    async fn fill_buf(&mut self) -> anyhow::Result<()> {
+        let value = self.state.value.read().await;
+        format::write(
+            self.config.format(),
+            &value,
+            self.config.pretty,
+            &mut self.buf,
+        )
+    }
+
+    /// Reloads the data if the file has been changed on disk. Returns whether there are still
+    /// in-memory changes waiting to be written.
+    async fn check_for_changes(&mut self, pending: bool) -> bool {
+        let versions = match files::versions(&self.config).await {
+            Ok(versions) => Some(versions),
+            Err(err) => {
+                log::warn!(
+                    "Failed to read metadata for file `{}`: {}",
+                    self.config.path.display(),
+                    err
+                );
+                return pending;
+            }
+        };
+        if versions == self.versions {
+            return pending;
+        }
+        self.versions = versions;
+
+        if pending {
+            match self.config.on_conflict {
+                config::JsonConflictPolicy::Memory => {
+                    log::warn!(
+                        "File `{}` changed on disk while there were unsaved changes, overwriting it",
+                        self.config.path.display()
+                    );
+                    return pending;
+                }
+                config::JsonConflictPolicy::File => {
+                    log::warn!(
+                        "File `{}` changed on disk while there were unsaved changes, discarding them",
+                        self.config.path.display()
+                    );
+                }
+            }
+        }
+
+        self.reload().await;
+        false
+    }
+
+    async fn reload(&mut self) {
+        log::info!(
+            "Reloading {} data from file `{}`",
+            self.config.format(),
+            self.config.path.display()
+        );
+
+        match files::read(&self.config).await {
+            Ok(value) => {
+                let mut current = self.state.value.write().await;
+                if let Value::Object(object) = &value {
+                    if self.config.is_dir() {
+                        self.synced = object.clone();
+                    }
+                }
+                *current = value;
+                self.state
+                    .publish(changes::operation("replace", "", Some(&current)));
+            }
+            Err(err) => log::error!("{:#}", err),
+        }
+    }
+}
+
+/// Writes to a temporary file alongside `path` and renames it into place, so the file is never
+/// left partially written.
+async fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp_path = temp_path(path);
+
+    let result = async {
+        let mut file = fs::File::create(&temp_path).await?;
+        if let Ok(metadata) = fs::metadata(path).await {
+            file.set_permissions(metadata.permissions()).await?;
+        }
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        drop(file);
+
+        fs::rename(&temp_path, path).await?;
+        sync_parent(path).await
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path).await;
+    }
+    result
+}
+
+/// Flushes the directory containing `path`, which makes a rename into it durable.
+#[cfg(unix)]
+async fn sync_parent(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    fs::File::open(parent).await?.sync_all().await
+}
+
+/// Directories can't be opened as files on other platforms, where renames are assumed to be
+/// durable.
+#[cfg(not(unix))]
+async fn sync_parent(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp_name = OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(".tmp");
+    path.with_file_name(temp_name)
+}
+
+#[cfg(test)]
+async fn json_handler(path: &Path, options: &str) -> super::JsonHandler {
+    std::fs::write(path, r#"{"items": [{"id": 1, "name": "first"}]}"#).unwrap();
+    let config =
+        serde_yaml::from_str(&format!("{{ path: '{}', {} }}", path.display(), options)).unwrap();
+    let registry = Arc::new(super::JsonRegistry::new(None));
+    super::JsonHandler::new(config, registry).await.unwrap()
+}
+
+#[cfg(test)]
+async fn put_name(handler: &super::JsonHandler, name: &str) {
+    let request = http::Request::put("/items/1")
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(hyper::Body::from(
+            serde_json::json!({ "id": 1, "name": name }).to_string(),
+        ))
+        .unwrap();
+    let response = handler.handle(request, "/items/1").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+}
+
+#[cfg(test)]
+fn file_name(path: &Path) -> Value {
+    let value: Value = serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+    value["items"][0]["name"].clone()
+}
+
+#[tokio::test]
+async fn test_write_atomic() {
+    let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    std::fs::create_dir(&dir).unwrap();
+    let path = dir.join("data.json");
+
+    write_atomic(&path, b"first").await.unwrap();
+    write_atomic(&path, b"second").await.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"second");
+    assert!(!temp_path(&path).exists());
+
+    // A failed write leaves neither the file nor a temporary file behind.
+    let missing = dir.join("missing").join("data.json");
+    assert!(write_atomic(&missing, b"first").await.is_err());
+    assert!(!temp_path(&missing).exists());
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_debounce() {
+    let path = std::env::temp_dir().join(format!("{}.json", uuid::Uuid::new_v4()));
+    let handler = json_handler(&path, "debounce-ms: 200").await;
+
+    put_name(&handler, "a").await;
+    put_name(&handler, "b").await;
+    time::delay_for(Duration::from_millis(50)).await;
+    put_name(&handler, "c").await;
+    assert_eq!(file_name(&path), "first");
+
+    time::delay_for(Duration::from_millis(400)).await;
+    assert_eq!(file_name(&path), "c");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_reload() {
+    for policy in &["file", "memory"] {
+        let path = std::env::temp_dir().join(format!("{}.json", uuid::Uuid::new_v4()));
+        let options = format!(
+            "watch: true, watch-interval-ms: 10, debounce-ms: 300, on-conflict: {}",
+            policy
+        );
+        let handler = json_handler(&path, &options).await;
+        let name = || async { handler.state.value.read().await["items"][0]["name"].clone() };
+
+        // Without unsaved changes, the file is reloaded whatever the policy.
+        std::fs::write(&path, r#"{"items": [{"id": 1, "name": "external"}]}"#).unwrap();
+        time::delay_for(Duration::from_millis(100)).await;
+        assert_eq!(name().await, "external");
+
+        // The change is left unsaved by the debounce, and conflicts with the file's.
+        put_name(&handler, "memory").await;
+        time::delay_for(Duration::from_millis(20)).await;
+        std::fs::write(&path, r#"{"items": [{"id": 1, "name": "external again"}]}"#).unwrap();
+        let expected = if *policy == "file" {
+            "external again"
+        } else {
+            "memory"
+        };
+        time::delay_for(Duration::from_millis(100)).await;
+        assert_eq!(name().await, expected);
+        time::delay_for(Duration::from_millis(400)).await;
+        assert_eq!(file_name(&path), expected);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use hyper::body::HttpBody;
+use hyper::Body;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time;
+
+use crate::{config, response};
+
+#[derive(Debug)]
+pub struct ConcurrencyLimiter {
+    semaphore: Arc<Semaphore>,
+    queue_timeout: Option<Duration>,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(config: config::ConcurrencyLimit) -> Self {
+        ConcurrencyLimiter {
+            semaphore: Arc::new(Semaphore::new(config.limit)),
+            queue_timeout: config.queue_timeout_ms.map(Duration::from_millis),
+        }
+    }
+
+    /// Waits for a free slot, or returns a `503 Service Unavailable` response if none is available
+    /// before the queue timeout elapses. Without a queue timeout, requests are rejected immediately.
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit, http::Response<Body>> {
+        let semaphore = self.semaphore.clone();
+        let permit = match self.queue_timeout {
+            Some(timeout) => time::timeout(timeout, semaphore.acquire_owned()).await.ok(),
+            None => semaphore.try_acquire_owned().ok(),
+        };
+
+        permit.ok_or_else(|| {
+            log::info!("Concurrency limit reached, rejecting request");
+            response::from_status(http::StatusCode::SERVICE_UNAVAILABLE)
+        })
+    }
+}
+
+/// Holds a permit until a response's body has been sent, so that the limit covers streaming the
+/// response as well as handling the request.
+pub fn hold_until_sent(
+    response: http::Response<Body>,
+    permit: OwnedSemaphorePermit,
+) -> http::Response<Body> {
+    let (mut parts, body) = response.into_parts();
+
+    // The wrapped body no longer knows its length, so keep it in a header, unless the status means
+    // there is no body.
+    let status = parts.status;
+    let has_body = !(status.is_informational()
+        || status == http::StatusCode::NO_CONTENT
+        || status == http::StatusCode::NOT_MODIFIED);
+    if let Some(len) = body.size_hint().exact().filter(|_| has_body) {
+        parts
+            .headers
+            .entry(http::header::CONTENT_LENGTH)
+            .or_insert_with(|| len.into());
+    }
+
+    let body = Body::wrap_stream(body.map(move |chunk| {
+        let _permit = &permit;
+        chunk
+    }));
+    http::Response::from_parts(parts, body)
+}
+
+#[tokio::test]
+async fn test_concurrency_limiter() {
+    let limiter = ConcurrencyLimiter::new(config::ConcurrencyLimit {
+        limit: 1,
+        queue_timeout_ms: None,
+    });
+    let permit = limiter.acquire().await.unwrap();
+    let rejected = limiter.acquire().await.unwrap_err();
+    assert_eq!(rejected.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+
+    // The permit is held until the body has been sent.
+    let response = hold_until_sent(http::Response::new(Body::from("body")), permit);
+    assert_eq!(response.headers()[http::header::CONTENT_LENGTH], "4");
+    assert!(limiter.acquire().await.is_err());
+    hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let permit = limiter.acquire().await.unwrap();
+
+    let mut response = http::Response::new(Body::empty());
+    *response.status_mut() = http::StatusCode::NO_CONTENT;
+    let response = hold_until_sent(response, permit);
+    assert!(!response
+        .headers()
+        .contains_key(http::header::CONTENT_LENGTH));
+}
+
+#[tokio::test]
+async fn test_concurrency_limiter_queue() {
+    let limiter = ConcurrencyLimiter::new(config::ConcurrencyLimit {
+        limit: 1,
+        queue_timeout_ms: Some(50),
+    });
+    let permit = limiter.acquire().await.unwrap();
+    let rejected = limiter.acquire().await.unwrap_err();
+    assert_eq!(rejected.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+
+    // A queued request gets the permit once it is released, within the timeout.
+    tokio::spawn(async move {
+        time::delay_for(Duration::from_millis(10)).await;
+        drop(permit);
+    });
+    assert!(limiter.acquire().await.is_ok());
+}
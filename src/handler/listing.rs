@@ -0,0 +1,186 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+use hyper::Body;
+use serde::Serialize;
+use tokio::fs;
+use tokio::io;
+use urlencoding::encode;
+
+use super::fs::join_relative;
+use super::policy::Policy;
+use crate::{config, response};
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    name: String,
+    /// A link to the entry, relative to the listing. Links to directories end with a `/`.
+    href: String,
+    #[serde(rename = "type")]
+    kind: EntryKind,
+    size: u64,
+    #[serde(serialize_with = "serialize_http_date")]
+    modified: Option<SystemTime>,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum EntryKind {
+    Directory,
+    File,
+}
+
+/// Returns a listing of the files in `dir` which `policy` allows to be listed. `request_path` is
+/// the path of the request, which must end with a `/` so that the links in the listing are
+/// relative to the directory, and `relative` is the path of `dir` relative to the route's root. A
+/// link to the parent directory is included unless `is_root` is set.
+pub async fn listing_response(
+    dir: &Path,
+    request_path: &str,
+    relative: &str,
+    is_root: bool,
+    format: config::DirListing,
+    policy: &Policy,
+) -> http::Response<Body> {
+    let entries = match read_entries(dir, relative, policy).await {
+        Ok(entries) => entries,
+        Err(err) => {
+            log::error!("Error listing directory `{}`: {}", dir.display(), err);
+            return response::from_status(http::StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    match format {
+        config::DirListing::Json => response::json(&entries),
+        config::DirListing::Html => {
+            let mut response =
+                http::Response::new(Body::from(html(&entries, request_path, is_root)));
+            response.headers_mut().insert(
+                http::header::CONTENT_TYPE,
+                http::HeaderValue::from_static("text/html; charset=utf-8"),
+            );
+            response
+        }
+    }
+}
+
+async fn read_entries(dir: &Path, relative: &str, policy: &Policy) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut read_dir = fs::read_dir(dir).await?;
+
+    while let Some(entry) = read_dir.next_entry().await? {
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(name) => {
+                log::warn!("Skipping file with invalid name {:?}", name);
+                continue;
+            }
+        };
+
+        // Follow symlinks, so that links to directories are listed as directories.
+        let metadata = match fs::metadata(entry.path()).await {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                log::warn!("Skipping file `{}`: {}", entry.path().display(), err);
+                continue;
+            }
+        };
+        let is_listed = policy
+            .is_listed(
+                &join_relative(relative, &name),
+                &entry.path(),
+                metadata.is_dir(),
+            )
+            .await;
+        if !is_listed {
+            continue;
+        }
+
+        let mut href = encode(&name);
+        if metadata.is_dir() {
+            href.push('/');
+        }
+        entries.push(Entry {
+            name,
+            href,
+            kind: if metadata.is_dir() {
+                EntryKind::Directory
+            } else {
+                EntryKind::File
+            },
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified().ok(),
+        });
+    }
+
+    entries.sort_by(|a, b| a.kind.cmp(&b.kind).then_with(|| a.name.cmp(&b.name)));
+    Ok(entries)
+}
+
+fn html(entries: &[Entry], request_path: &str, is_root: bool) -> String {
+    let title = format!("Index of {}", escape_html(request_path));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n</head>\n\
+         <body>\n<h1>{0}</h1>\n<table>\n\
+         <tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n",
+        title
+    );
+    if !is_root {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+
+    for entry in entries {
+        let (suffix, size) = match entry.kind {
+            EntryKind::Directory => ("/", String::new()),
+            EntryKind::File => ("", entry.size.to_string()),
+        };
+        let modified = entry.modified.map(httpdate::fmt_http_date);
+        writeln!(
+            html,
+            "<tr><td><a href=\"{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>",
+            entry.href,
+            escape_html(&entry.name),
+            suffix,
+            size,
+            modified.unwrap_or_default(),
+        )
+        .unwrap();
+    }
+
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn serialize_http_date<S>(time: &Option<SystemTime>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match time {
+        Some(time) => serializer.serialize_str(&httpdate::fmt_http_date(*time)),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[test]
+fn test_escape_html() {
+    assert_eq!(
+        escape_html("<a href=\"x\">Tom & Jerry's</a>"),
+        "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+    );
+}
@@ -1,19 +1,35 @@
+mod admin;
+mod archive;
+mod content_type;
 mod fs;
 mod json;
+mod limit;
+mod listing;
 mod mock;
+mod policy;
 mod proxy;
+mod range;
+mod writable;
 
 use std::fmt;
+use std::sync::Arc;
 
 use anyhow::Result;
 use hyper::Body;
 
+use self::admin::AdminHandler;
+use self::archive::ArchiveHandler;
 use self::fs::{DirHandler, FileHandler};
 use self::json::JsonHandler;
+use self::limit::ConcurrencyLimiter;
 use self::mock::MockHandler;
 use self::proxy::ProxyHandler;
+
+pub use self::json::JsonRegistry;
+
 use crate::method::MethodFilter;
 use crate::path::PathRewriter;
+use crate::schema::Schema;
 use crate::{config, response};
 
 pub struct Handler {
@@ -21,25 +37,31 @@ pub struct Handler {
     path_rewriter: Option<PathRewriter>,
     response_headers: http::HeaderMap,
     method_filter: Box<dyn MethodFilter>,
+    concurrency_limiter: Option<ConcurrencyLimiter>,
+    request_schema: Option<Schema>,
 }
 
 #[derive(Debug)]
 pub enum HandlerKind {
     File(FileHandler),
     Dir(DirHandler),
+    Archive(ArchiveHandler),
     Proxy(ProxyHandler),
     Json(JsonHandler),
     Mock(MockHandler),
+    Admin(AdminHandler),
 }
 
 impl Handler {
-    pub async fn new(route: config::Route) -> Result<Self> {
+    pub async fn new(route: config::Route, json_registry: Arc<JsonRegistry>) -> Result<Self> {
         let config::Route {
             rewrite_path,
             route,
             kind,
             response_headers,
             methods,
+            max_concurrent,
+            request_schema,
         } = route;
         let path_rewriter = rewrite_path.map(|replace| {
             let regex = route.to_regex();
@@ -48,10 +70,18 @@ impl Handler {
 
         let kind = match kind {
             config::RouteKind::File(file) => HandlerKind::File(FileHandler::new(file)),
-            config::RouteKind::Dir(dir) => HandlerKind::Dir(DirHandler::new(dir)),
+            config::RouteKind::Dir(dir) => HandlerKind::Dir(DirHandler::new(dir)?),
+            config::RouteKind::Archive(archive) => {
+                HandlerKind::Archive(ArchiveHandler::new(archive).await?)
+            }
             config::RouteKind::Proxy(proxy) => HandlerKind::Proxy(ProxyHandler::new(proxy)),
-            config::RouteKind::Json(json) => HandlerKind::Json(JsonHandler::new(json).await?),
+            config::RouteKind::Json(json) => {
+                HandlerKind::Json(JsonHandler::new(json, json_registry.clone()).await?)
+            }
             config::RouteKind::Mock(mock) => HandlerKind::Mock(MockHandler::new(mock)),
+            config::RouteKind::Admin(admin) => {
+                HandlerKind::Admin(AdminHandler::new(admin, json_registry))
+            }
         };
 
         let method_filter = match methods {
@@ -59,11 +89,16 @@ impl Handler {
             None => kind.default_method_filter(),
         };
 
+        let concurrency_limiter = max_concurrent.map(ConcurrencyLimiter::new);
+        let request_schema = request_schema.map(|path| Schema::load(&path)).transpose()?;
+
         Ok(Handler {
             path_rewriter,
             kind,
             response_headers,
             method_filter,
+            concurrency_limiter,
+            request_schema,
         })
     }
 
@@ -78,6 +113,24 @@ impl Handler {
             ));
         }
 
+        let permit = match &self.concurrency_limiter {
+            Some(limiter) => match limiter.acquire().await {
+                Ok(permit) => Some(permit),
+                Err(response) => return Ok(response),
+            },
+            None => None,
+        };
+
+        let request = match &self.request_schema {
+            Some(schema) if has_body(request.method()) => {
+                match schema.check_request(request).await {
+                    Ok(request) => request,
+                    Err(response) => return Ok(response),
+                }
+            }
+            _ => request,
+        };
+
         let path = match &self.path_rewriter {
             Some(path_rewriter) => path_rewriter.rewrite(request.uri().path()),
             None => request.uri().path().to_owned(),
@@ -86,36 +139,52 @@ impl Handler {
         let mut result = match &self.kind {
             HandlerKind::File(file) => file.handle(request).await,
             HandlerKind::Dir(dir) => dir.handle(request, &path).await,
+            HandlerKind::Archive(archive) => archive.handle(request, &path).await,
             HandlerKind::Proxy(proxy) => proxy.handle(request, &path).await,
             HandlerKind::Json(json) => json.handle(request, &path).await,
             HandlerKind::Mock(mock) => mock.handle(request).await,
+            HandlerKind::Admin(admin) => admin.handle(request, &path).await,
         };
 
         if let Ok(response) = &mut result {
             response.headers_mut().extend(self.response_headers.clone());
         }
 
-        result
+        match (result, permit) {
+            (Ok(response), Some(permit)) => Ok(limit::hold_until_sent(response, permit)),
+            (result, _) => result,
+        }
     }
 }
 
 impl HandlerKind {
     fn default_method_filter(&self) -> Box<dyn MethodFilter> {
         match self {
-            HandlerKind::File(_) | HandlerKind::Dir(_) => fs::default_method_filter(),
+            HandlerKind::File(_) | HandlerKind::Archive(_) => fs::default_method_filter(),
+            HandlerKind::Dir(dir) => dir.default_method_filter(),
             HandlerKind::Proxy(_) => proxy::default_method_filter(),
             HandlerKind::Json(_) => json::default_method_filter(),
             HandlerKind::Mock(_) => mock::default_method_filter(),
+            HandlerKind::Admin(_) => admin::default_method_filter(),
         }
     }
 }
 
+fn has_body(method: &http::Method) -> bool {
+    !matches!(
+        *method,
+        http::Method::GET | http::Method::HEAD | http::Method::OPTIONS | http::Method::DELETE
+    )
+}
+
 impl fmt::Debug for Handler {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Handler")
             .field("kind", &self.kind)
             .field("path_rewriter", &self.path_rewriter)
             .field("response_headers", &self.response_headers)
+            .field("concurrency_limiter", &self.concurrency_limiter)
+            .field("request_schema", &self.request_schema)
             .finish()
     }
 }
@@ -0,0 +1,165 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use regex::RegexSet;
+use tokio::fs;
+use tokio::io::{self, ErrorKind};
+
+use crate::config::{self, Dotfiles, Symlinks};
+use crate::glob;
+
+/// Decides which files in a dir route may be accessed.
+#[derive(Debug)]
+pub struct Policy {
+    root: PathBuf,
+    canonical_root: PathBuf,
+    dotfiles: Option<Dotfiles>,
+    symlinks: Symlinks,
+    allow: Option<RegexSet>,
+    deny: RegexSet,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    Allowed,
+    /// The path should be treated as if it doesn't exist.
+    Hidden,
+    Forbidden,
+}
+
+impl Policy {
+    pub fn new(config: &config::DirRoute) -> Result<Self> {
+        let canonical_root = config
+            .path
+            .canonicalize()
+            .with_context(|| format!("failed to resolve directory `{}`", config.path.display()))?;
+        Ok(Policy {
+            root: config.path.clone(),
+            canonical_root,
+            dotfiles: config.dotfiles,
+            symlinks: config.symlinks,
+            allow: if config.allow.is_empty() {
+                None
+            } else {
+                Some(glob::regex_set(&config.allow))
+            },
+            deny: glob::regex_set(&config.deny),
+        })
+    }
+
+    /// Checks whether a path may be accessed. `relative` is the path relative to the root of the
+    /// route, with segments separated by `/`, and `path` is the corresponding file system path.
+    /// The allow patterns only apply to files, so `is_dir` should be set for directories.
+    pub async fn check(&self, relative: &str, path: &Path, is_dir: bool) -> Access {
+        if relative.split('/').any(is_hidden) {
+            match self.dotfiles {
+                None | Some(Dotfiles::Allow) => (),
+                Some(Dotfiles::Ignore) => return Access::Hidden,
+                Some(Dotfiles::Deny) => return Access::Forbidden,
+            }
+        }
+
+        // Files in a denied directory are also denied.
+        let mut prefixes = relative
+            .match_indices('/')
+            .map(|(index, _)| &relative[..index])
+            .chain(Some(relative));
+        if prefixes.any(|prefix| self.deny.is_match(prefix)) {
+            return Access::Forbidden;
+        }
+        if let Some(allow) = &self.allow {
+            if !is_dir && !allow.is_match(relative) {
+                return Access::Forbidden;
+            }
+        }
+
+        let symlinks_allowed = match self.symlinks {
+            Symlinks::Follow => Ok(true),
+            Symlinks::Confine => self.is_confined(path).await,
+            Symlinks::Deny => self.has_no_symlinks(relative).await,
+        };
+        match symlinks_allowed {
+            Ok(true) => Access::Allowed,
+            Ok(false) => {
+                log::info!("Path `{}` is refused by the symlink policy", relative);
+                Access::Forbidden
+            }
+            Err(err) => {
+                log::error!("Error resolving path `{}`: {}", path.display(), err);
+                Access::Forbidden
+            }
+        }
+    }
+
+    /// Checks whether an entry in a directory listing should be shown. Hidden files are left out
+    /// unless dotfiles are explicitly allowed.
+    pub async fn is_listed(&self, relative: &str, path: &Path, is_dir: bool) -> bool {
+        if self.dotfiles.is_none() && relative.split('/').any(is_hidden) {
+            return false;
+        }
+        self.check(relative, path, is_dir).await == Access::Allowed
+    }
+
+    /// Checks whether a path may be created, replaced or removed. Hidden files can only be written
+    /// if dotfiles are explicitly allowed. Writes always stay within the root directory, whatever
+    /// the symlink policy, so the directory containing the path must resolve to somewhere inside
+    /// it. The path itself isn't resolved, since writes replace or remove a symlink rather than
+    /// following it.
+    pub async fn is_writable(&self, relative: &str, path: &Path) -> bool {
+        if self.dotfiles != Some(Dotfiles::Allow) && relative.split('/').any(is_hidden) {
+            log::info!("Refusing to write hidden file `{}`", relative);
+            return false;
+        }
+
+        let parent = match path.parent() {
+            Some(parent) => parent,
+            None => return false,
+        };
+        match self.is_confined(parent).await {
+            Ok(true) => true,
+            Ok(false) => {
+                log::info!("Refusing to write `{}` outside of the root directory", relative);
+                false
+            }
+            Err(err) => {
+                log::error!("Error resolving path `{}`: {}", parent.display(), err);
+                false
+            }
+        }
+    }
+
+    /// Returns whether the target of a path is within the root directory, after resolving any
+    /// symlinks. Paths which don't exist yet are checked by their nearest existing ancestor.
+    async fn is_confined(&self, path: &Path) -> io::Result<bool> {
+        let mut path = path;
+        loop {
+            match fs::canonicalize(path).await {
+                Ok(canonical) => return Ok(canonical.starts_with(&self.canonical_root)),
+                Err(err) if err.kind() == ErrorKind::NotFound => match path.parent() {
+                    Some(parent) => path = parent,
+                    None => return Ok(false),
+                },
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Returns whether none of the segments of a path below the root directory are symlinks.
+    async fn has_no_symlinks(&self, relative: &str) -> io::Result<bool> {
+        let mut path = self.root.clone();
+        for segment in relative.split('/').filter(|segment| !segment.is_empty()) {
+            path.push(segment);
+            match fs::symlink_metadata(&path).await {
+                Ok(metadata) if metadata.file_type().is_symlink() => return Ok(false),
+                Ok(_) => (),
+                Err(err) if err.kind() == ErrorKind::NotFound => return Ok(true),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(true)
+    }
+}
+
+fn is_hidden(segment: &str) -> bool {
+    segment.starts_with('.')
+}
@@ -0,0 +1,110 @@
+use std::ops::Bound;
+
+use headers::{ETag, HeaderMapExt, IfRange, LastModified, Range};
+
+/// The most ranges served in a single response. Requests for more ranges get the full body, so
+/// that a client can't make the server do excessive work for a small request.
+const MAX_RANGES: usize = 32;
+
+/// The part of a representation to send in response to a request.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ranges {
+    Full,
+    /// Inclusive byte ranges, in the order requested.
+    Partial(Vec<(u64, u64)>),
+    Unsatisfiable,
+}
+
+/// Resolves the `Range` and `If-Range` headers of a request for a representation of `len` bytes.
+pub fn resolve(
+    headers: &http::HeaderMap,
+    len: u64,
+    etag: Option<&ETag>,
+    last_modified: Option<&LastModified>,
+) -> Ranges {
+    let range = match headers.typed_get::<Range>() {
+        Some(range) => range,
+        None => return Ranges::Full,
+    };
+    if let Some(if_range) = headers.typed_get::<IfRange>() {
+        if if_range.is_modified(etag, last_modified) {
+            return Ranges::Full;
+        }
+    }
+
+    let specs: Vec<_> = range.iter().collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return Ranges::Full;
+    }
+
+    let ranges: Vec<(u64, u64)> = specs
+        .into_iter()
+        .filter_map(|spec| satisfiable_range(spec, len))
+        .collect();
+    if ranges.is_empty() {
+        Ranges::Unsatisfiable
+    } else {
+        Ranges::Partial(ranges)
+    }
+}
+
+/// Normalizes a byte range spec into an inclusive range within a representation of `len` bytes,
+/// following RFC 7233 section 2.1.
+fn satisfiable_range((start, end): (Bound<u64>, Bound<u64>), len: u64) -> Option<(u64, u64)> {
+    if len == 0 {
+        return None;
+    }
+    match (start, end) {
+        (Bound::Included(start), Bound::Included(end)) if start < len && start <= end => {
+            Some((start, end.min(len - 1)))
+        }
+        (Bound::Included(start), Bound::Unbounded) if start < len => Some((start, len - 1)),
+        (Bound::Unbounded, Bound::Included(suffix)) if suffix > 0 => {
+            Some((len.saturating_sub(suffix), len - 1))
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn test_resolve() {
+    fn resolve_range(range: &str, len: u64) -> Ranges {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(http::header::RANGE, range.parse().unwrap());
+        resolve(&headers, len, None, None)
+    }
+
+    assert_eq!(
+        resolve_range("bytes=0-9", 100),
+        Ranges::Partial(vec![(0, 9)])
+    );
+    assert_eq!(
+        resolve_range("bytes=90-", 100),
+        Ranges::Partial(vec![(90, 99)])
+    );
+    assert_eq!(
+        resolve_range("bytes=-10", 100),
+        Ranges::Partial(vec![(90, 99)])
+    );
+    assert_eq!(
+        resolve_range("bytes=-200", 100),
+        Ranges::Partial(vec![(0, 99)])
+    );
+    assert_eq!(
+        resolve_range("bytes=50-200", 100),
+        Ranges::Partial(vec![(50, 99)])
+    );
+    assert_eq!(
+        resolve_range("bytes=0-0, 200-300, -1", 100),
+        Ranges::Partial(vec![(0, 0), (99, 99)])
+    );
+    assert_eq!(resolve_range("bytes=100-", 100), Ranges::Unsatisfiable);
+    assert_eq!(resolve_range("bytes=0-", 0), Ranges::Unsatisfiable);
+    assert_eq!(resolve_range("items=0-1", 100), Ranges::Full);
+
+    let mut headers = http::HeaderMap::new();
+    headers.insert(http::header::RANGE, "bytes=0-9".parse().unwrap());
+    headers.insert(http::header::IF_RANGE, "\"old\"".parse().unwrap());
+    let etag: ETag = "\"new\"".parse().unwrap();
+    assert_eq!(resolve(&headers, 100, Some(&etag), None), Ranges::Full);
+}
@@ -0,0 +1,348 @@
+use std::path::{Path, PathBuf};
+
+use futures::StreamExt;
+use headers::{ContentLength, HeaderMapExt};
+use hyper::Body;
+use once_cell::sync::Lazy;
+use tokio::fs;
+use tokio::io::{self, AsyncWriteExt, ErrorKind};
+use uuid::Uuid;
+
+use super::fs::is_dir;
+use crate::{config, response};
+
+pub static MKCOL: Lazy<http::Method> =
+    Lazy::new(|| http::Method::from_bytes(b"MKCOL").expect("MKCOL is a valid method"));
+
+/// The outcome of writing a request body to a file.
+enum Upload {
+    Complete,
+    TooLarge,
+    Aborted(hyper::Error),
+}
+
+/// Returns whether a dir route accepts requests with `method` that modify its files.
+pub fn is_write_method(config: &config::DirRoute, method: &http::Method) -> bool {
+    config.writable
+        && (method == http::Method::PUT
+            || method == http::Method::DELETE
+            || (config.mkcol && method == *MKCOL))
+}
+
+/// Handles a `PUT`, `DELETE` or `MKCOL` request for `path` in a writable dir route. The root
+/// directory of the route itself can't be modified.
+pub async fn write_response(
+    request: http::Request<Body>,
+    path: &Path,
+    is_root: bool,
+    config: &config::DirRoute,
+) -> http::Response<Body> {
+    if is_root {
+        log::info!("Refusing to modify the root directory of a dir route");
+        return response::from_status(http::StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    let result = if request.method() == http::Method::PUT {
+        put(request, path, config).await
+    } else if request.method() == http::Method::DELETE {
+        delete(path, config).await
+    } else {
+        mkcol(path).await
+    };
+    match result {
+        Ok(response) => response,
+        Err(err) => {
+            log::error!("Error modifying `{}`: {}", path.display(), err);
+            response::from_status(http::StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Creates or replaces a file with the body of a request. The body is written to a temporary file
+/// first, so that readers never see a partial upload.
+async fn put(
+    request: http::Request<Body>,
+    path: &Path,
+    config: &config::DirRoute,
+) -> io::Result<http::Response<Body>> {
+    if let Some(ContentLength(len)) = request.headers().typed_get() {
+        if len > config.max_upload_size {
+            log::info!("Upload to `{}` is too large", path.display());
+            return Ok(response::from_status(http::StatusCode::PAYLOAD_TOO_LARGE));
+        }
+    }
+
+    let exists = match fs::metadata(path).await {
+        Ok(metadata) if metadata.is_dir() => {
+            log::info!("Can't upload to directory `{}`", path.display());
+            return Ok(response::from_status(http::StatusCode::CONFLICT));
+        }
+        Ok(_) => true,
+        Err(err) if err.kind() == ErrorKind::NotFound => false,
+        Err(err) => return Err(err),
+    };
+
+    let parent = path.parent().expect("path is within the route's directory");
+    if !is_dir(parent).await {
+        if !config.mkcol {
+            log::info!("Directory `{}` does not exist", parent.display());
+            return Ok(response::from_status(http::StatusCode::CONFLICT));
+        }
+        if let Err(err) = fs::create_dir_all(parent).await {
+            log::info!("Can't create directory `{}`: {}", parent.display(), err);
+            return Ok(response::from_status(http::StatusCode::CONFLICT));
+        }
+    }
+
+    let temp_path = temp_path(path);
+    let upload = write_body(request.into_body(), &temp_path, config.max_upload_size).await;
+    let upload = match upload {
+        Ok(Upload::Complete) => fs::rename(&temp_path, path).await,
+        Ok(Upload::TooLarge) => {
+            log::info!("Upload to `{}` is too large", path.display());
+            remove_temp_file(&temp_path).await;
+            return Ok(response::from_status(http::StatusCode::PAYLOAD_TOO_LARGE));
+        }
+        Ok(Upload::Aborted(err)) => {
+            log::info!("Upload to `{}` failed: {}", path.display(), err);
+            remove_temp_file(&temp_path).await;
+            return Ok(response::from_status(http::StatusCode::BAD_REQUEST));
+        }
+        Err(err) => Err(err),
+    };
+    if let Err(err) = upload {
+        remove_temp_file(&temp_path).await;
+        return Err(err);
+    }
+
+    log::debug!("Uploaded file `{}`", path.display());
+    Ok(response::from_status(if exists {
+        http::StatusCode::NO_CONTENT
+    } else {
+        http::StatusCode::CREATED
+    }))
+}
+
+async fn write_body(mut body: Body, path: &Path, max_size: u64) -> io::Result<Upload> {
+    let mut file = fs::File::create(path).await?;
+    let mut size = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => return Ok(Upload::Aborted(err)),
+        };
+        size += chunk.len() as u64;
+        if size > max_size {
+            return Ok(Upload::TooLarge);
+        }
+        file.write_all(&chunk).await?;
+    }
+    file.sync_all().await?;
+    Ok(Upload::Complete)
+}
+
+/// Returns the path of a hidden file next to `path`, to upload to before renaming.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().expect("path has a file name"));
+    name.push(format!(".{}.upload", Uuid::new_v4()));
+    path.with_file_name(name)
+}
+
+async fn remove_temp_file(path: &Path) {
+    if let Err(err) = fs::remove_file(path).await {
+        log::warn!("Error removing `{}`: {}", path.display(), err);
+    }
+}
+
+/// Removes a file or a directory. Directories must be empty unless `recursive-delete` is set.
+/// Symlinks are removed rather than followed.
+async fn delete(path: &Path, config: &config::DirRoute) -> io::Result<http::Response<Body>> {
+    let result = match fs::symlink_metadata(path).await {
+        Ok(metadata) if metadata.is_dir() && config.recursive_delete => {
+            fs::remove_dir_all(path).await
+        }
+        Ok(metadata) if metadata.is_dir() => {
+            if fs::read_dir(path).await?.next_entry().await?.is_some() {
+                log::info!("Directory `{}` is not empty", path.display());
+                return Ok(response::from_status(http::StatusCode::CONFLICT));
+            }
+            fs::remove_dir(path).await
+        }
+        Ok(_) => fs::remove_file(path).await,
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(()) => {
+            log::debug!("Deleted `{}`", path.display());
+            Ok(response::from_status(http::StatusCode::NO_CONTENT))
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            log::info!("File not found: `{}`", path.display());
+            Ok(response::from_status(http::StatusCode::NOT_FOUND))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Creates a directory, following the status codes of RFC 4918 section 9.3.1.
+async fn mkcol(path: &Path) -> io::Result<http::Response<Body>> {
+    let parent = path.parent().expect("path is within the route's directory");
+    if !is_dir(parent).await {
+        log::info!("Directory `{}` does not exist", parent.display());
+        return Ok(response::from_status(http::StatusCode::CONFLICT));
+    }
+
+    match fs::create_dir(path).await {
+        Ok(()) => {
+            log::debug!("Created directory `{}`", path.display());
+            Ok(response::from_status(http::StatusCode::CREATED))
+        }
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+            log::info!("`{}` already exists", path.display());
+            Ok(response::from_status(http::StatusCode::METHOD_NOT_ALLOWED))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+#[tokio::test]
+async fn test_write_requests() {
+    use super::fs::DirHandler;
+
+    async fn send(
+        handler: &DirHandler,
+        method: &http::Method,
+        path: &str,
+        body: &'static str,
+    ) -> http::StatusCode {
+        let request = http::Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::from(body))
+            .unwrap();
+        match handler.handle(request, path).await {
+            Ok(response) => response.status(),
+            Err((_, response)) => response.status(),
+        }
+    }
+
+    let temp_dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    let root = temp_dir.join("root");
+    let outside = temp_dir.join("outside");
+    std::fs::create_dir_all(root.join("full")).unwrap();
+    std::fs::create_dir_all(root.join("empty")).unwrap();
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(root.join("full/file.txt"), "data").unwrap();
+    std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+
+    let handler = |options: &str| {
+        let config: config::DirRoute = serde_yaml::from_str(&format!(
+            "{{ path: '{}', writable: true, max-upload-size: 8, {} }}",
+            root.display(),
+            options
+        ))
+        .unwrap();
+        DirHandler::new(config).unwrap()
+    };
+    let dir = handler("");
+    let put = &http::Method::PUT;
+    let delete = &http::Method::DELETE;
+
+    assert_eq!(
+        send(&dir, put, "/new.txt", "one").await,
+        http::StatusCode::CREATED
+    );
+    assert_eq!(
+        send(&dir, put, "/new.txt", "two").await,
+        http::StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        std::fs::read_to_string(root.join("new.txt")).unwrap(),
+        "two"
+    );
+    assert_eq!(
+        send(&dir, put, "/big.txt", "more than eight bytes").await,
+        http::StatusCode::PAYLOAD_TOO_LARGE
+    );
+    assert!(!root.join("big.txt").exists());
+    assert_eq!(
+        send(&dir, put, "/missing/a.txt", "").await,
+        http::StatusCode::CONFLICT
+    );
+    assert_eq!(
+        send(&dir, put, "/full", "").await,
+        http::StatusCode::CONFLICT
+    );
+
+    // Writes never leave the root directory, even though symlinks are followed for reads.
+    assert_eq!(
+        send(&dir, put, "/link/a.txt", "").await,
+        http::StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        send(&dir, delete, "/link/x", "").await,
+        http::StatusCode::FORBIDDEN
+    );
+    assert_eq!(std::fs::read_dir(&outside).unwrap().count(), 0);
+    assert_eq!(
+        send(&dir, put, "/.git/hooks/post-checkout", "").await,
+        http::StatusCode::FORBIDDEN
+    );
+    assert!(!root.join(".git").exists());
+
+    assert_eq!(
+        send(&dir, delete, "/full", "").await,
+        http::StatusCode::CONFLICT
+    );
+    assert_eq!(
+        send(&dir, delete, "/empty", "").await,
+        http::StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        send(&dir, delete, "/new.txt", "").await,
+        http::StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        send(&dir, delete, "/new.txt", "").await,
+        http::StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        send(&dir, delete, "/link", "").await,
+        http::StatusCode::NO_CONTENT
+    );
+    assert!(outside.exists());
+    assert_eq!(
+        send(&dir, &MKCOL, "/sub", "").await,
+        http::StatusCode::METHOD_NOT_ALLOWED
+    );
+
+    let dir = handler("mkcol: true, recursive-delete: true, dotfiles: allow");
+    assert_eq!(
+        send(&dir, &MKCOL, "/sub", "").await,
+        http::StatusCode::CREATED
+    );
+    assert_eq!(
+        send(&dir, &MKCOL, "/sub", "").await,
+        http::StatusCode::METHOD_NOT_ALLOWED
+    );
+    assert_eq!(
+        send(&dir, &MKCOL, "/a/b", "").await,
+        http::StatusCode::CONFLICT
+    );
+    assert_eq!(
+        send(&dir, put, "/c/d.txt", "").await,
+        http::StatusCode::CREATED
+    );
+    assert_eq!(
+        send(&dir, put, "/.env", "").await,
+        http::StatusCode::CREATED
+    );
+    assert_eq!(
+        send(&dir, delete, "/full", "").await,
+        http::StatusCode::NO_CONTENT
+    );
+    assert!(!root.join("full").exists());
+
+    std::fs::remove_dir_all(&temp_dir).unwrap();
+}
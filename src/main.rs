@@ -1,11 +1,14 @@
 use structopt::StructOpt;
 
+mod compress;
 mod config;
+mod glob;
 mod handler;
 mod method;
 mod path;
 mod response;
 mod route;
+mod schema;
 mod server;
 mod tls;
 
@@ -15,3 +15,16 @@ pub fn json<T: Serialize>(value: &T) -> http::Response<Body> {
     response.headers_mut().typed_insert(ContentType::json());
     response
 }
+
+pub fn set_header(
+    response: &mut http::Response<Body>,
+    name: http::header::HeaderName,
+    value: &str,
+) {
+    match http::HeaderValue::from_str(value) {
+        Ok(value) => {
+            response.headers_mut().insert(name, value);
+        }
+        Err(err) => log::warn!("Invalid value `{}` for header `{}`: {}", value, name, err),
+    }
+}
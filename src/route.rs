@@ -14,14 +14,16 @@ use once_cell::sync::Lazy;
 use regex::{Regex, RegexSet};
 use serde::de::{self, Deserialize, Deserializer};
 
+use crate::compress::Compression;
 use crate::config::Config;
-use crate::handler::Handler;
+use crate::handler::{Handler, JsonRegistry};
 use crate::response;
 
 #[derive(Debug)]
 pub struct Router {
     regex_set: RegexSet,
     handlers: Vec<Handler>,
+    compression: Option<Compression>,
 }
 
 #[derive(Debug)]
@@ -42,11 +44,20 @@ impl Router {
         config.routes.sort_by_key(|route| route.route.precedence);
         let regex_set = RegexSet::new(config.routes.iter().map(|route| &route.route.regex))
             .expect("error in generated regex");
-        let handlers = future::try_join_all(config.routes.into_iter().map(Handler::new)).await?;
+        let json_registry = Arc::new(JsonRegistry::new(config.sessions));
+        json_registry.spawn_session_purge();
+        let handlers = future::try_join_all(
+            config
+                .routes
+                .into_iter()
+                .map(|route| Handler::new(route, json_registry.clone())),
+        )
+        .await?;
 
         Ok(Router {
             regex_set,
             handlers,
+            compression: config.compression.map(Compression::new),
         })
     }
 }
@@ -56,6 +67,21 @@ impl Router {
         self: Arc<Self>,
         mut request: http::Request<Body>,
     ) -> http::Response<Body> {
+        let accept_encoding = request
+            .headers()
+            .get(http::header::ACCEPT_ENCODING)
+            .cloned();
+        if let Some(compression) = &self.compression {
+            compression.strip_etag_suffixes(request.headers_mut());
+        }
+        let response = self.dispatch(request).await;
+        match &self.compression {
+            Some(compression) => compression.compress(accept_encoding.as_ref(), response),
+            None => response,
+        }
+    }
+
+    async fn dispatch(&self, mut request: http::Request<Body>) -> http::Response<Body> {
         let mut response = response::from_status(http::StatusCode::NOT_FOUND);
 
         let matches = self.regex_set.matches(request.uri().path());
@@ -217,3 +243,68 @@ fn test_precedence() {
         }
     );
 }
+
+#[tokio::test]
+async fn test_compressed_if_match() {
+    let path = std::env::temp_dir().join(format!("{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, r#"{"items": [{"id": 1, "name": "first"}]}"#).unwrap();
+    let config: Config = serde_yaml::from_str(&format!(
+        "{{ compression: {{ min-size: 1 }}, routes: [{{ route: '/**', kind: json, path: '{}' }}] }}",
+        path.display()
+    ))
+    .unwrap();
+    let router = Arc::new(Router::new(config).await.unwrap());
+
+    let request = http::Request::get("/items/1")
+        .header(http::header::ACCEPT_ENCODING, "gzip")
+        .body(Body::empty())
+        .unwrap();
+    let response = router.clone().try_handle(request).await;
+    assert_eq!(response.headers()[http::header::CONTENT_ENCODING], "gzip");
+    let etag = response.headers()[http::header::ETAG].clone();
+    assert!(etag.to_str().unwrap().ends_with(";gzip\""));
+
+    let request = http::Request::put("/items/1")
+        .header(http::header::IF_MATCH, etag)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"id": 1, "name": "second"}"#))
+        .unwrap();
+    let response = router.try_handle(request).await;
+    assert_eq!(response.status(), http::StatusCode::OK);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_compressed_if_range() {
+    let path = std::env::temp_dir().join(format!("{}.txt", uuid::Uuid::new_v4()));
+    std::fs::write(&path, "hello, world! ".repeat(10)).unwrap();
+    let config: Config = serde_yaml::from_str(&format!(
+        "{{ compression: {{ min-size: 1 }}, routes: [{{ route: '/a.txt', kind: file, path: '{}' }}] }}",
+        path.display()
+    ))
+    .unwrap();
+    let router = Arc::new(Router::new(config).await.unwrap());
+
+    let request = http::Request::get("/a.txt")
+        .header(http::header::ACCEPT_ENCODING, "gzip")
+        .body(Body::empty())
+        .unwrap();
+    let response = router.clone().try_handle(request).await;
+    assert_eq!(response.headers()[http::header::CONTENT_ENCODING], "gzip");
+    assert!(!response.headers().contains_key(http::header::ACCEPT_RANGES));
+    let etag = response.headers()[http::header::ETAG].clone();
+
+    // The range would be of the uncompressed file, so the whole compressed body is sent instead.
+    let request = http::Request::get("/a.txt")
+        .header(http::header::ACCEPT_ENCODING, "gzip")
+        .header(http::header::RANGE, "bytes=10-")
+        .header(http::header::IF_RANGE, etag)
+        .body(Body::empty())
+        .unwrap();
+    let response = router.try_handle(request).await;
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(response.headers()[http::header::CONTENT_ENCODING], "gzip");
+
+    std::fs::remove_file(&path).unwrap();
+}
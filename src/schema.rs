@@ -0,0 +1,165 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{format_err, Context, Result};
+use hyper::body::{self, Body};
+use serde::Serialize;
+use serde_json::Value;
+use url::Url;
+use valico::json_schema::Scope;
+
+use crate::response;
+
+/// A compiled JSON Schema, loaded from a JSON or YAML file.
+pub struct Schema {
+    path: PathBuf,
+    // The compiled schema caches resolved references internally, so it is not `Sync`.
+    scope: Mutex<Scope>,
+    url: Url,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Violation {
+    pub pointer: String,
+    pub message: String,
+}
+
+impl Schema {
+    pub fn load(path: &Path) -> Result<Self> {
+        let buf = fs::read(path)
+            .with_context(|| format!("failed to read schema from `{}`", path.display()))?;
+        let value: Value = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => serde_yaml::from_slice(&buf)?,
+            _ => serde_json::from_slice(&buf)?,
+        };
+
+        let mut scope = Scope::new();
+        let url = scope
+            .compile(value, false)
+            .map_err(|err| format_err!("invalid schema `{}`: {:?}", path.display(), err))?;
+
+        Ok(Schema {
+            path: path.to_owned(),
+            scope: Mutex::new(scope),
+            url,
+        })
+    }
+
+    pub fn validate(&self, value: &Value) -> Result<(), Vec<Violation>> {
+        let scope = self.scope.lock().unwrap();
+        let schema = scope
+            .resolve(&self.url)
+            .expect("compiled schema is in scope");
+        let state = schema.validate(value);
+        if state.is_strictly_valid() {
+            return Ok(());
+        }
+
+        let mut violations: Vec<Violation> = state
+            .errors
+            .iter()
+            .map(|error| Violation {
+                pointer: error.get_path().to_owned(),
+                message: match error.get_detail() {
+                    Some(detail) => format!("{}: {}", error.get_title(), detail),
+                    None => error.get_title().to_owned(),
+                },
+            })
+            .collect();
+        violations.extend(state.missing.iter().map(|url| Violation {
+            pointer: String::new(),
+            message: format!("unresolved schema reference `{}`", url),
+        }));
+        Err(violations)
+    }
+
+    /// Checks the body of a request against the schema, returning the request with its body
+    /// restored if it is valid.
+    pub async fn check_request(
+        &self,
+        request: http::Request<Body>,
+    ) -> Result<http::Request<Body>, http::Response<Body>> {
+        let (parts, body) = request.into_parts();
+        let bytes = match body::to_bytes(body).await {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                log::error!("Error reading request body: {}", err);
+                return Err(response::from_status(
+                    http::StatusCode::INTERNAL_SERVER_ERROR,
+                ));
+            }
+        };
+
+        let value = match serde_json::from_slice(&bytes) {
+            Ok(value) => value,
+            Err(err) => {
+                log::info!("Error deserializing request body: {}", err);
+                return Err(response::from_status(http::StatusCode::BAD_REQUEST));
+            }
+        };
+        self.validate(&value).map_err(violations_response)?;
+
+        Ok(http::Request::from_parts(parts, Body::from(bytes)))
+    }
+}
+
+/// Returns a `422 Unprocessable Entity` response with an `application/problem+json` body listing
+/// the schema violations.
+pub fn violations_response(violations: Vec<Violation>) -> http::Response<Body> {
+    log::info!("Schema validation failed: {:?}", violations);
+
+    let status = http::StatusCode::UNPROCESSABLE_ENTITY;
+    let mut response = response::json(&serde_json::json!({
+        "type": "about:blank",
+        "title": status.canonical_reason(),
+        "status": status.as_u16(),
+        "detail": "The request does not conform to the schema",
+        "violations": violations,
+    }));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_static("application/problem+json"),
+    );
+    response
+}
+
+impl fmt::Debug for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Schema").field("path", &self.path).finish()
+    }
+}
+
+#[test]
+fn test_validate() {
+    let mut scope = Scope::new();
+    let url = scope
+        .compile(
+            serde_json::json!({
+                "type": "object",
+                "properties": { "age": { "type": "integer", "minimum": 0 } },
+                "required": ["name"],
+            }),
+            false,
+        )
+        .unwrap();
+    let schema = Schema {
+        path: PathBuf::new(),
+        scope: Mutex::new(scope),
+        url,
+    };
+
+    assert!(schema
+        .validate(&serde_json::json!({ "name": "a", "age": 3 }))
+        .is_ok());
+
+    let violations = schema
+        .validate(&serde_json::json!({ "age": -1 }))
+        .unwrap_err();
+    assert_eq!(violations.len(), 2);
+    assert!(violations
+        .iter()
+        .any(|violation| violation.pointer == "/age"));
+}